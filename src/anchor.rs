@@ -0,0 +1,26 @@
+//! Types for reading the `Anchor.toml` of an Anchor workspace.
+use anyhow::{format_err, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AnchorConfig {
+    /// Program addresses, keyed by cluster and then by program name.
+    #[serde(default)]
+    pub programs: BTreeMap<String, BTreeMap<String, String>>,
+    pub provider: Option<Provider>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Provider {
+    pub wallet: String,
+}
+
+impl AnchorConfig {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|e| format_err!("Unable to deserialize {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,227 @@
+//! Building programs with Anchor or cargo.
+use crate::command;
+use crate::config::BuildCommand;
+use crate::workspace;
+use anyhow::{anyhow, Result};
+use colored::*;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Docker image used for verifiable builds of non-Anchor workspaces. Matches the
+/// image used by `anchor build --verifiable`.
+pub const VERIFIABLE_BUILD_IMAGE: &str = "projectserum/build:v0.14.0";
+
+/// Whether the installed toolchain has `cargo build-sbf`.
+pub fn has_build_sbf() -> bool {
+    Command::new("cargo")
+        .arg("build-sbf")
+        .arg("--help")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Builds the programs of the workspace, or only `program` if given. Without Anchor,
+/// `build_command` is used, or else `cargo build-sbf` if the toolchain has it.
+pub fn build(
+    root: &Path,
+    program: Option<&str>,
+    verifiable: bool,
+    features: &[String],
+    build_command: Option<BuildCommand>,
+) -> Result<()> {
+    let features = features.join(",");
+    if root.join("Anchor.toml").exists() {
+        println!(
+            "{}",
+            format!(
+                "Anchor found! Running `anchor build{}`.",
+                if verifiable { " --verifiable" } else { "" }
+            )
+            .green()
+        );
+        let mut args = vec![];
+        if verifiable {
+            args.push("--verifiable");
+        }
+        if let Some(program) = program {
+            args.extend(&["-p", program]);
+        }
+        // Anchor passes arguments after `--` on to cargo.
+        if !features.is_empty() {
+            args.extend(&["--", "--features", features.as_str()]);
+        }
+        command::exec(
+            Command::new("anchor")
+                .current_dir(root)
+                .arg("build")
+                .args(args),
+        )?;
+        if verifiable {
+            // Anchor leaves verifiable builds in target/verifiable, but deploys and
+            // verification use target/deploy.
+            let verifiable_dir = root.join("target").join("verifiable");
+            let deploy_dir = root.join("target").join("deploy");
+            for entry in std::fs::read_dir(&verifiable_dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ex| ex.to_str()) != Some("so") {
+                    continue;
+                }
+                if let Some(file_name) = path.file_name() {
+                    std::fs::copy(&path, deploy_dir.join(file_name))?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // The verifiable build image predates `cargo build-sbf`.
+    let build_command = build_command.unwrap_or(if verifiable || !has_build_sbf() {
+        BuildCommand::BuildBpf
+    } else {
+        BuildCommand::BuildSbf
+    });
+    let build_args = cargo_build_args(root, program, verifiable, &features, build_command)?;
+
+    if verifiable {
+        println!(
+            "{}",
+            format!(
+                "Anchor.toml not found in workspace root. Running `cargo {}` in {}.",
+                build_command, VERIFIABLE_BUILD_IMAGE
+            )
+            .yellow()
+        );
+        command::exec(
+            Command::new("docker")
+                .arg("run")
+                .arg("--rm")
+                .arg("--volume")
+                .arg(format!("{}:/workdir", root.display()))
+                .arg("--workdir")
+                .arg("/workdir")
+                .arg(VERIFIABLE_BUILD_IMAGE)
+                .arg("cargo")
+                .args(build_args),
+        )?;
+    } else {
+        println!(
+            "{}",
+            format!(
+                "Anchor.toml not found in workspace root. Running `cargo {}`.",
+                build_command
+            )
+            .yellow()
+        );
+        command::exec(Command::new("cargo").current_dir(root).args(build_args))?;
+    }
+    Ok(())
+}
+
+/// Arguments to cargo to build `program`, or every program, outside of Anchor.
+pub fn cargo_build_args(
+    root: &Path,
+    program: Option<&str>,
+    verifiable: bool,
+    features: &str,
+    build_command: BuildCommand,
+) -> Result<Vec<OsString>> {
+    // `cargo build-sbf` selects a single package by its manifest.
+    let mut build_args = vec![build_command.to_string().into()];
+    if let Some(program) = program {
+        build_args.push("--manifest-path".into());
+        let manifest_path = workspace::get_program_manifest_path(program, root)?;
+        build_args.push(if verifiable {
+            // Paths are inside the container, where the root is mounted at /workdir.
+            Path::new("/workdir")
+                .join(manifest_path.strip_prefix(root)?)
+                .into_os_string()
+        } else {
+            manifest_path.into_os_string()
+        });
+    }
+    if !features.is_empty() {
+        build_args.push("--features".into());
+        build_args.push(features.into());
+    }
+    Ok(build_args)
+}
+
+/// Builds each program with its features on its own with cargo, `jobs` at a time,
+/// prefixing each line of output with the program name. Every program gets its own
+/// target directory under `target/captain/`, since cargo only runs one build per
+/// target directory at a time, and the binaries are written to `target/deploy/`.
+pub fn build_parallel(
+    root: &Path,
+    programs: &[(String, Vec<String>)],
+    build_command: Option<BuildCommand>,
+    jobs: usize,
+) -> Result<()> {
+    let build_command = build_command.unwrap_or(if has_build_sbf() {
+        BuildCommand::BuildSbf
+    } else {
+        BuildCommand::BuildBpf
+    });
+    let out_dir_flag = match build_command {
+        BuildCommand::BuildSbf => "--sbf-out-dir",
+        BuildCommand::BuildBpf => "--bpf-out-dir",
+    };
+    println!(
+        "{}",
+        format!(
+            "Anchor.toml not found in workspace root. Running `cargo {}` for each program, up to {} at a time.",
+            build_command, jobs
+        )
+        .yellow()
+    );
+
+    let next = AtomicUsize::new(0);
+    let failed = Mutex::new(vec![]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(programs.len()) {
+            scope.spawn(|| {
+                while let Some((program, features)) =
+                    programs.get(next.fetch_add(1, Ordering::SeqCst))
+                {
+                    let result = cargo_build_args(
+                        root,
+                        Some(program),
+                        false,
+                        &features.join(","),
+                        build_command,
+                    )
+                    .and_then(|build_args| {
+                        command::exec_with_prefix(
+                            Command::new("cargo")
+                                .current_dir(root)
+                                .env(
+                                    "CARGO_TARGET_DIR",
+                                    root.join("target").join("captain").join(program),
+                                )
+                                .args(build_args)
+                                .arg(out_dir_flag)
+                                .arg(root.join("target").join("deploy")),
+                            program,
+                        )
+                    });
+                    if let Err(err) = result {
+                        eprintln!("{}", format!("Failed to build {}: {}", program, err).red());
+                        if let Ok(mut failed) = failed.lock() {
+                            failed.push(program.as_str());
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner().unwrap_or_default();
+    if !failed.is_empty() {
+        return Err(anyhow!("Failed to build {}", failed.join(", ")));
+    }
+    Ok(())
+}
@@ -1,20 +1,459 @@
+use crate::error::CaptainError;
+use crate::rpc::{Backoff, Commitment};
 use anyhow::{format_err, Result};
+use colored::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader};
 use std::process::Command;
+use std::process::ExitStatus;
 use std::process::Output;
 use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Number of trailing stderr lines included in the error of a failed command.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// If set, [exec] prints commands instead of running them.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::SeqCst);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// If set, the stdout of commands is sent to stderr so that our stdout only
+/// contains machine-readable output.
+static STDOUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn set_stdout_to_stderr(redirect: bool) {
+    STDOUT_TO_STDERR.store(redirect, Ordering::SeqCst);
+}
+
+fn child_stdout() -> Stdio {
+    if STDOUT_TO_STDERR.load(Ordering::SeqCst) {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+/// If set, [exec_with_progress] shows a spinner while the command runs.
+static PROGRESS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_progress(progress: bool) {
+    PROGRESS.store(progress, Ordering::SeqCst);
+}
+
+/// If set, the installed Solana CLI is not checked against `solana_version`.
+static SKIP_VERSION_CHECK: AtomicBool = AtomicBool::new(false);
+
+pub fn set_skip_version_check(skip: bool) {
+    SKIP_VERSION_CHECK.store(skip, Ordering::SeqCst);
+}
+
+pub fn skip_version_check() -> bool {
+    SKIP_VERSION_CHECK.load(Ordering::SeqCst)
+}
+
+/// Commitment passed to every solana command. Defaults to confirmed.
+static COMMITMENT: OnceLock<Commitment> = OnceLock::new();
+
+pub fn set_commitment(commitment: Commitment) {
+    // Only set once, while parsing the command line.
+    let _ = COMMITMENT.set(commitment);
+}
+
+pub fn commitment() -> Commitment {
+    COMMITMENT.get().copied().unwrap_or(Commitment::Confirmed)
+}
+
+/// Seconds after which [exec] kills a command, or 0 to wait forever.
+static TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_timeout_secs(secs: u64) {
+    TIMEOUT_SECS.store(secs, Ordering::SeqCst);
+}
+
+/// Times [exec_with_progress] re-runs a command which failed with an expired blockhash,
+/// independently of its `max_retries`.
+static BLOCKHASH_RETRIES: AtomicU32 = AtomicU32::new(0);
+
+pub fn set_blockhash_retries(retries: u32) {
+    BLOCKHASH_RETRIES.store(retries, Ordering::SeqCst);
+}
+
+/// How long to wait before retrying a command which failed with a transient error.
+const RETRY_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_secs(2),
+    max: Duration::from_secs(32),
+};
+
+/// How often a running command is checked for having exited or timed out.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A spinner with the elapsed time, drawn to stderr. Hidden if stderr is not a terminal.
+fn spinner(message: &str) -> ProgressBar {
+    let spinner = ProgressBar::new_spinner();
+    if let Ok(style) = ProgressStyle::default_spinner().template("{spinner} {msg} ({elapsed})") {
+        spinner.set_style(style);
+    }
+    spinner.set_message(message.to_string());
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// Prints a line of child output, hiding the spinner while doing so.
+fn print_line(line: &str, to_stderr: bool, spinner: Option<&ProgressBar>, prefix: Option<&str>) {
+    let line = match prefix {
+        Some(prefix) => format!("{} {}", format!("[{}]", prefix).bold(), line),
+        None => line.to_string(),
+    };
+    let print = || {
+        if to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    match spinner {
+        Some(spinner) => spinner.suspend(print),
+        None => print(),
+    }
+}
+
+/// Where to get the executables Captain runs, for the error when one is missing.
+const INSTALL_HINTS: &[(&str, &str)] = &[
+    ("solana", "https://docs.solanalabs.com/cli/install"),
+    ("anchor", "https://www.anchor-lang.com/docs/installation"),
+];
+
+/// Errors with install instructions if `program` is not an executable on the PATH.
+pub fn require(program: &str) -> Result<()> {
+    let found = std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()));
+    if found {
+        return Ok(());
+    }
+    let hint = INSTALL_HINTS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .map(|(_, url)| format!("; install it from {}", url))
+        .unwrap_or_default();
+    Err(format_err!("{} CLI not found on PATH{}", program, hint))
+}
+
+/// A command which exited unsuccessfully.
+#[derive(Debug)]
+pub struct CommandError {
+    pub command: String,
+    pub status: ExitStatus,
+    /// The last lines the command wrote to stderr.
+    pub stderr: Vec<String>,
+}
+
+impl CommandError {
+    /// Returns true if the transaction was not confirmed before its blockhash expired.
+    pub fn is_blockhash_expired(&self) -> bool {
+        self.stderr
+            .iter()
+            .any(|line| line.to_lowercase().contains("blockhash expired"))
+    }
+
+    /// Returns true if the program data account is too small for the new program.
+    pub fn is_account_too_small(&self) -> bool {
+        self.stderr
+            .iter()
+            .any(|line| line.to_lowercase().contains("account data too small"))
+    }
+
+    /// Returns true if the failure looks like a transient RPC problem, i.e. an expired
+    /// blockhash, a timeout, or rate limiting.
+    pub fn is_transient(&self) -> bool {
+        self.stderr.iter().any(|line| {
+            let line = line.to_lowercase();
+            line.contains("blockhash")
+                || line.contains("timed out")
+                || line.contains("rate limit")
+                || line.contains("429 too many requests")
+                || line.contains("status code: 429")
+        })
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` failed with {}\n{}",
+            self.command,
+            self.status,
+            self.stderr.join("\n")
+        )
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Returns the failed command behind the error, if any.
+pub fn command_error(err: &anyhow::Error) -> Option<&CommandError> {
+    match err.downcast_ref::<CaptainError>() {
+        Some(CaptainError::CommandFailed(command_err)) => Some(command_err),
+        _ => None,
+    }
+}
+
+/// Formats the command as it would be typed into a shell.
+pub fn format_command(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|s| s.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
 pub fn exec_unhandled(command: &mut Command) -> Result<Output> {
+    log::debug!("Running {}", format_command(command));
     command
-        .stdout(Stdio::inherit())
+        .stdout(child_stdout())
         .stderr(Stdio::inherit())
         .output()
         .map_err(|e| format_err!("Error deploying: {}", e.to_string()))
 }
 
-pub fn exec(command: &mut Command) -> Result<Output> {
-    let exit = exec_unhandled(command)?;
-    if !exit.status.success() {
-        std::process::exit(exit.status.code().unwrap_or(1));
+/// Runs the command and returns its stdout. Only used for read-only queries, so
+/// these run even in dry run mode.
+pub fn exec_output(command: &mut Command) -> Result<String> {
+    log::debug!("Running {}", format_command(command));
+    let output = command
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format_err!("Error running {}: {}", format_command(command), e))?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "`{}` failed with {}",
+            format_command(command),
+            output.status
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs the command, streaming its output. If the command fails, the returned error
+/// contains the command line and the last lines it wrote to stderr.
+pub fn exec(command: &mut Command) -> Result<()> {
+    run(command, false, None, None)?;
+    Ok(())
+}
+
+/// Like [exec], but prefixes every line of output with `[prefix]`, so the output of
+/// commands running at the same time can be told apart.
+pub fn exec_with_prefix(command: &mut Command, prefix: &str) -> Result<()> {
+    run(command, false, None, Some(prefix))?;
+    Ok(())
+}
+
+/// Runs the command like [exec_capture], retrying up to `max_retries` times with
+/// jittered exponential backoff if it fails with a transient error.
+pub fn exec_with_retries(command: &mut Command, max_retries: u32) -> Result<String> {
+    retry(command, max_retries, None)
+}
+
+/// Like [exec_with_retries], but shows `message` and the elapsed time in a spinner
+/// while the command runs, for commands which can take minutes. These are the
+/// program deploys and buffer writes, which the Solana CLI resumes where they left
+/// off, so a failure with an expired blockhash is also re-run right away up to
+/// [set_blockhash_retries] times.
+pub fn exec_with_progress(
+    command: &mut Command,
+    max_retries: u32,
+    message: &str,
+) -> Result<String> {
+    retry(command, max_retries, Some(message))
+}
+
+fn retry(command: &mut Command, max_retries: u32, progress: Option<&str>) -> Result<String> {
+    let max_blockhash_retries = match progress {
+        Some(_) => BLOCKHASH_RETRIES.load(Ordering::SeqCst),
+        None => 0,
+    };
+    let mut retries = 0;
+    let mut blockhash_retries = 0;
+    loop {
+        let err = match run(command, true, progress, None) {
+            Ok(stdout) => return Ok(stdout),
+            Err(err) => err,
+        };
+        let command_err = command_error(&err);
+        if command_err.is_some_and(CommandError::is_blockhash_expired)
+            && blockhash_retries < max_blockhash_retries
+        {
+            blockhash_retries += 1;
+            eprintln!(
+                "{}",
+                format!(
+                    "Blockhash expired, re-running ({}/{})",
+                    blockhash_retries, max_blockhash_retries
+                )
+                .yellow()
+            );
+            continue;
+        }
+        let transient = command_err.is_some_and(CommandError::is_transient);
+        if !transient || retries >= max_retries {
+            return Err(err);
+        }
+        let delay = RETRY_BACKOFF.jittered_delay(retries);
+        retries += 1;
+        eprintln!(
+            "{}",
+            format!(
+                "Transient failure, retrying in {:.1}s ({}/{})",
+                delay.as_secs_f64(),
+                retries,
+                max_retries
+            )
+            .yellow()
+        );
+        std::thread::sleep(delay);
+    }
+}
+
+/// Like [exec], but also returns everything the command wrote to stdout. Returns an
+/// empty string in dry run mode.
+pub fn exec_capture(command: &mut Command) -> Result<String> {
+    run(command, true, None, None)
+}
+
+fn run(
+    command: &mut Command,
+    capture_stdout: bool,
+    progress: Option<&str>,
+    prefix: Option<&str>,
+) -> Result<String> {
+    let redirect_stdout = STDOUT_TO_STDERR.load(Ordering::SeqCst);
+    if is_dry_run() {
+        let line = format!("{} {}", "[dry run]".yellow(), format_command(command));
+        if redirect_stdout {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+        return Ok(String::new());
+    }
+    log::debug!("Running {}", format_command(command));
+    let mut child = command
+        // Prefixed output has to go through the readers to be prefixed.
+        .stdout(if capture_stdout || prefix.is_some() {
+            Stdio::piped()
+        } else {
+            child_stdout()
+        })
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format_err!("Error running {}: {}", format_command(command), e))?;
+    let spinner = progress
+        .filter(|_| PROGRESS.load(Ordering::SeqCst))
+        .map(spinner);
+
+    let stderr = child.stderr.take();
+    let stderr_spinner = spinner.clone();
+    let stderr_prefix = prefix.map(str::to_string);
+    let stderr_reader = std::thread::spawn(move || -> std::io::Result<Vec<String>> {
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        if let Some(stderr) = stderr {
+            for line in BufReader::new(stderr).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                print_line(
+                    &line,
+                    true,
+                    stderr_spinner.as_ref(),
+                    stderr_prefix.as_deref(),
+                );
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+        }
+        Ok(tail.into())
+    });
+
+    let stdout = child.stdout.take();
+    let stdout_spinner = spinner.clone();
+    let stdout_prefix = prefix.map(str::to_string);
+    let stdout_reader = std::thread::spawn(move || -> std::io::Result<String> {
+        let mut stdout_buf = String::new();
+        if let Some(stdout) = stdout {
+            for line in BufReader::new(stdout).split(b'\n') {
+                let line = String::from_utf8_lossy(&line?).into_owned();
+                print_line(
+                    &line,
+                    redirect_stdout,
+                    stdout_spinner.as_ref(),
+                    stdout_prefix.as_deref(),
+                );
+                stdout_buf.push_str(&line);
+                stdout_buf.push('\n');
+            }
+        }
+        Ok(stdout_buf)
+    });
+
+    // Output keeps streaming from the reader threads while we wait.
+    let timeout = match TIMEOUT_SECS.load(Ordering::SeqCst) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            child.kill()?;
+            child.wait()?;
+            // Something else may still hold the pipes open, so do not wait for the readers.
+            if let Some(spinner) = &spinner {
+                spinner.finish_and_clear();
+            }
+            return Err(format_err!(
+                "`{}` did not finish within {}s and was killed. Use --command-timeout to allow more time.",
+                format_command(command),
+                started.elapsed().as_secs()
+            ));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let stdout_buf = stdout_reader
+        .join()
+        .map_err(|_| format_err!("stdout reader of {} panicked", format_command(command)))??;
+    let stderr_tail = stderr_reader
+        .join()
+        .map_err(|_| format_err!("stderr reader of {} panicked", format_command(command)))??;
+    if let Some(spinner) = spinner {
+        spinner.finish_and_clear();
+    }
+    log::trace!(
+        "{} exited with {} after {}s",
+        format_command(command),
+        status,
+        started.elapsed().as_secs()
+    );
+    if !status.success() {
+        return Err(CaptainError::from(CommandError {
+            command: format_command(command),
+            status,
+            stderr: stderr_tail,
+        })
+        .into());
     }
-    Ok(exit)
+    Ok(stdout_buf)
 }
@@ -1,16 +1,82 @@
+use crate::error::CaptainError;
 use anyhow::{anyhow, format_err, Error, Result};
 use cargo_toml::Manifest;
-use semver::Version;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
-use serde_with::{DeserializeFromStr, SerializeDisplay};
+use serde_with::{serde_as, DeserializeFromStr, DisplayFromStr, OneOrMany, SerializeDisplay};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
 use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use strum_macros::{AsRefStr, Display, EnumString, EnumVariantNames, IntoStaticStr};
 
+/// The most compute units a transaction may request.
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Cargo subcommand used to build programs outside of Anchor.
+#[derive(
+    Clone, Copy, Debug, Display, EnumString, PartialEq, SerializeDisplay, DeserializeFromStr,
+)]
+#[strum(serialize_all = "kebab-case")]
+pub enum BuildCommand {
+    BuildSbf,
+    /// Deprecated in favor of `build-sbf`, but the only one in old toolchains.
+    BuildBpf,
+}
+
+/// What to do when deploying to mainnet from a git tree with uncommitted changes.
+#[derive(
+    Clone, Copy, Debug, Display, EnumString, PartialEq, SerializeDisplay, DeserializeFromStr,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum DirtyTreePolicy {
+    Warn,
+    Error,
+}
+
+/// Where the version of a program comes from when `--version` is not passed.
+#[derive(Clone, Debug, PartialEq, SerializeDisplay, DeserializeFromStr)]
+pub enum VersionSourceConfig {
+    /// The version of the program package in its Cargo.toml.
+    Cargo,
+    /// The latest tag reachable from HEAD, with an optional leading `v`.
+    GitTag,
+    /// A file containing just the version, relative to the workspace root.
+    File(PathBuf),
+}
+
+impl FromStr for VersionSourceConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cargo" => Ok(VersionSourceConfig::Cargo),
+            "git-tag" => Ok(VersionSourceConfig::GitTag),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(VersionSourceConfig::File(path.into())),
+                _ => Err(anyhow!(
+                    "invalid version source `{}`: expected `cargo`, `git-tag` or `file:<path>`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for VersionSourceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionSourceConfig::Cargo => write!(f, "cargo"),
+            VersionSourceConfig::GitTag => write!(f, "git-tag"),
+            VersionSourceConfig::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
 #[derive(
     AsRefStr,
     Clone,
@@ -29,6 +95,9 @@ use strum_macros::{AsRefStr, Display, EnumString, EnumVariantNames, IntoStaticSt
 #[strum(serialize_all = "lowercase")]
 pub enum Network {
     Testnet,
+    /// Serialized as `mainnet-beta`, the cluster name used by the Solana CLI.
+    /// `mainnet` is still accepted when parsing.
+    #[strum(serialize = "mainnet-beta", serialize = "mainnet")]
     Mainnet,
     Devnet,
     Localnet,
@@ -56,11 +125,53 @@ impl Network {
     }
 }
 
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub paths: Paths,
+    /// Network used when `--network` is not passed. Defaults to devnet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_network: Option<Network>,
+    /// Deployer of networks which do not set their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployer: Option<CaptainPath>,
+    /// Versions of the Anchor CLI the workspace supports, e.g. `0.30`.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor_version: Option<VersionReq>,
+    /// Versions of the Solana CLI the workspace supports, e.g. `~1.18`.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solana_version: Option<VersionReq>,
+    /// Cargo subcommand used to build programs without Anchor. Detected if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_command: Option<BuildCommand>,
+    /// Where program versions come from when `--version` is not passed. Defaults to
+    /// the program's Cargo.toml.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_source: Option<VersionSourceConfig>,
+    /// Whether deploying to mainnet with uncommitted changes warns or fails. Defaults to
+    /// warning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dirty_tree: Option<DirtyTreePolicy>,
+    /// Largest program binary, in bytes, that may be deployed or upgraded to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_program_size: Option<u64>,
+    /// Programs left out when building or deploying all programs. `*` matches any
+    /// characters and `?` matches one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Shell command run before deploying or upgrading a program.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_deploy: Option<String>,
+    /// Shell command run after a program was deployed or upgraded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_deploy: Option<String>,
     /// Network configuration
     pub networks: BTreeMap<Network, NetworkConfig>,
+    /// Program configuration, keyed by program name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub programs: BTreeMap<String, ProgramConfig>,
 }
 
 impl Default for Config {
@@ -70,28 +181,112 @@ impl Default for Config {
                 artifacts: CaptainPath(PathBuf::from("./.captain/artifacts/")),
                 program_keypairs: CaptainPath(PathBuf::from("./.captain/program_keypairs")),
             },
+            default_network: None,
+            deployer: None,
+            anchor_version: None,
+            solana_version: None,
+            build_command: None,
+            version_source: None,
+            dirty_tree: None,
+            max_program_size: None,
+            exclude: vec![],
+            pre_deploy: None,
+            post_deploy: None,
             networks: BTreeMap::default(),
+            programs: BTreeMap::default(),
         }
     }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Paths {
-    /// Where binaries are stored
+    /// Where binaries are stored. Also accepted as `artifacts_dir`.
+    #[serde(alias = "artifacts_dir")]
     pub artifacts: CaptainPath,
     /// Where program address keypairs are stored
     pub program_keypairs: CaptainPath,
 }
 
+#[serde_as]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ProgramConfig {
+    /// Address the program is expected to be deployed to.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<Pubkey>,
+    /// Cargo features enabled whenever the program is built.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
+    /// Additional cargo features enabled when building for a network.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub network_features: BTreeMap<Network, Vec<String>>,
+    /// Path to the IDL of the program, relative to the workspace root. Defaults to
+    /// `target/idl/<program>.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idl: Option<CaptainPath>,
+    /// Networks the program may be deployed to. Any network if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<Network>,
+}
+
+#[serde_as]
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct NetworkConfig {
+    /// Keypair paying for deploys. Defaults to the top-level `deployer`.
+    #[serde(default, skip_serializing_if = "CaptainPath::is_empty")]
     pub deployer: CaptainPath,
-    /// The upgrade authority address.
-    pub upgrade_authority: String,
-    /// URL
+    /// The upgrade authority address, or a list of them while rotating authorities:
+    /// the first is set on deploy, and the others are still accepted on chain.
+    #[serde_as(as = "OneOrMany<_>")]
+    #[serde(rename = "upgrade_authority")]
+    pub upgrade_authorities: Vec<String>,
+    /// RPC URL. Defaults to the cluster URL of the network. Also accepted as `rpc_url`.
+    #[serde(alias = "rpc_url")]
     pub url: Option<String>,
     /// Websocket URL
     pub ws_url: Option<String>,
+    /// Default compute unit price, in micro-lamports, of deploy transactions.
+    pub priority_fee: Option<u64>,
+    /// Default compute unit limit of deploy transactions.
+    pub compute_unit_limit: Option<u32>,
+    /// Replaces the top-level `pre_deploy` hook on this network.
+    pub pre_deploy: Option<String>,
+    /// Replaces the top-level `post_deploy` hook on this network.
+    pub post_deploy: Option<String>,
+}
+
+/// Resolves an upgrade authority, which may be either a pubkey or the path to a keypair.
+fn resolve_authority(authority: &str) -> Result<Pubkey> {
+    if let Ok(pubkey) = Pubkey::from_str(authority) {
+        return Ok(pubkey);
+    }
+    let path = shellexpand::tilde(authority).to_string();
+    Ok(solana_sdk::signer::keypair::read_keypair_file(&path)
+        .map_err(|_| format_err!("could not read upgrade authority keypair {}", path))?
+        .pubkey())
+}
+
+impl NetworkConfig {
+    /// The upgrade authority to set, as written in Captain.toml.
+    pub fn upgrade_authority(&self) -> &str {
+        self.upgrade_authorities
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Resolves the upgrade authority to set.
+    pub fn upgrade_authority_pubkey(&self) -> Result<Pubkey> {
+        resolve_authority(self.upgrade_authority())
+    }
+
+    /// Resolves every upgrade authority that may be on chain, starting with the one to set.
+    pub fn accepted_upgrade_authorities(&self) -> Result<Vec<Pubkey>> {
+        self.upgrade_authorities
+            .iter()
+            .map(|authority| resolve_authority(authority))
+            .collect()
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -99,6 +294,8 @@ pub struct ArtifactPaths {
     pub root: PathBuf,
     pub bin: PathBuf,
     pub idl: PathBuf,
+    /// SHA-256 of the binary, written when the artifacts are released.
+    pub sha256: PathBuf,
 }
 
 impl ArtifactPaths {
@@ -115,6 +312,28 @@ impl Config {
             .ok_or_else(|| format_err!("network {} not found", network))
     }
 
+    /// Whether the program matches one of the `exclude` patterns.
+    pub fn is_excluded(&self, program: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|pattern| wildcard_match(pattern.as_bytes(), program.as_bytes()))
+    }
+
+    /// Cargo features to build the program with for the given network.
+    pub fn build_features(&self, program: &str, network: Option<&Network>) -> Vec<String> {
+        let program_config = match self.programs.get(program) {
+            Some(program_config) => program_config,
+            None => return vec![],
+        };
+        let mut features = program_config.features.clone();
+        if let Some(network_features) =
+            network.and_then(|network| program_config.network_features.get(network))
+        {
+            features.extend(network_features.iter().cloned());
+        }
+        features
+    }
+
     /// Path to the keypair of a program.
     pub fn program_kp_path(&self, version: &Version, program: &str) -> PathBuf {
         self.paths
@@ -135,45 +354,366 @@ impl Config {
             root: root.clone(),
             bin: root.join("program.so"),
             idl: root.join("idl.json"),
+            sha256: root.join(format!("{}-{}.sha256", program, version)),
+        }
+    }
+
+    /// Versions of a program that have been released into the artifacts directory,
+    /// sorted by semver.
+    pub fn artifact_versions(&self, program: &str) -> Result<Vec<Version>> {
+        let program_dir = self.paths.artifacts.0.join(program);
+        if !program_dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut versions = vec![];
+        for entry in fs::read_dir(program_dir)? {
+            let name = entry?.file_name();
+            if let Some(version) = name.to_str().and_then(|n| Version::parse(n).ok()) {
+                if self.artifact_paths(&version, program).bin.exists() {
+                    versions.push(version);
+                }
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+
+    /// Checks the fields serde cannot, returning an error naming every problem found.
+    pub fn validate(&self, path: &Path) -> Result<()> {
+        let mut problems = vec![];
+        if self.networks.is_empty() {
+            problems.push("no networks are configured under `[networks]`".to_string());
+        }
+        for (network, network_config) in &self.networks {
+            if network_config.deployer.is_empty() && self.deployer.is_none() {
+                problems.push(format!(
+                    "`networks.{}.deployer` is empty and there is no top-level `deployer`",
+                    network
+                ));
+            }
+            if network_config.upgrade_authorities.is_empty()
+                || network_config
+                    .upgrade_authorities
+                    .iter()
+                    .any(|authority| authority.trim().is_empty())
+            {
+                problems.push(format!("`networks.{}.upgrade_authority` is empty", network));
+            }
+            if let Some(limit) = network_config.compute_unit_limit {
+                if limit > MAX_COMPUTE_UNIT_LIMIT {
+                    problems.push(format!(
+                        "`networks.{}.compute_unit_limit` is {}, but at most {} compute units may be requested",
+                        network, limit, MAX_COMPUTE_UNIT_LIMIT
+                    ));
+                }
+            }
+        }
+        if let Some(network) = &self.default_network {
+            if !self.networks.contains_key(network) {
+                problems.push(format!(
+                    "`default_network` is {}, which is not configured under `[networks]`",
+                    network
+                ));
+            }
+        }
+        for program in self.programs.keys() {
+            if program.is_empty()
+                || !program
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                problems.push(format!(
+                    "`programs.{}` is not a valid program name; use the name of its target/deploy/<name>.so",
+                    program
+                ));
+            }
         }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "{} is invalid:\n{}",
+            path.display(),
+            problems
+                .iter()
+                .map(|p| format!("  - {}", p))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ))
+    }
+
+    /// Loads the config at `config_path`, or searches all parent directories for a
+    /// Captain.toml if no path is given.
+    pub fn discover(config_path: Option<&Path>) -> Result<(Self, Manifest, PathBuf)> {
+        Self::load(&Self::find(config_path)?)
     }
 
-    // Searches all parent directories for a Captain.toml and Cargo.toml file.
-    pub fn discover() -> Result<(Self, Manifest, PathBuf)> {
-        // Set to true if we ever see a Cargo.toml file when traversing the
-        // parent directories.
+    /// Path to the Captain.toml in use: `config_path` if given, otherwise the first one
+    /// found in the current directory or its parents.
+    pub fn find(config_path: Option<&Path>) -> Result<PathBuf> {
+        if let Some(config_path) = config_path {
+            if !config_path.exists() {
+                return Err(anyhow!("Config {} does not exist", config_path.display()));
+            }
+            return Ok(config_path.to_path_buf());
+        }
 
         let _cwd = std::env::current_dir()?;
         let mut cwd_opt = Some(_cwd.as_path());
 
         while let Some(cwd) = cwd_opt {
-            let files = fs::read_dir(cwd)?;
-            // Cargo.toml file for this directory level.
-            for f in files {
-                let p = f?.path();
-                if let Some(filename) = p.file_name() {
-                    if filename.to_str() == Some("Captain.toml") {
-                        let mut cfg_file = File::open(&p)?;
-                        let mut cfg_contents = String::new();
-                        cfg_file.read_to_string(&mut cfg_contents)?;
-                        let cfg = cfg_contents.parse()?;
-                        let cwd_buf = cwd.to_path_buf();
-                        return Ok((
-                            cfg,
-                            Manifest::from_path(cwd_buf.join("Cargo.toml"))?,
-                            cwd_buf,
-                        ));
-                    }
-                }
+            let captain_toml = cwd.join("Captain.toml");
+            if captain_toml.exists() {
+                return Ok(captain_toml);
             }
 
             cwd_opt = cwd.parent();
         }
 
-        Err(anyhow!("Cargo.toml and Captain.toml not found"))
+        Err(CaptainError::ConfigNotFound.into())
+    }
+
+    /// Expands `${VAR}` placeholders in URLs, keypair paths and authorities, so that
+    /// secrets do not have to be committed.
+    fn expand_env(&mut self) -> Result<()> {
+        self.paths.artifacts.expand_env("paths.artifacts")?;
+        self.paths
+            .program_keypairs
+            .expand_env("paths.program_keypairs")?;
+        for (network, network_config) in self.networks.iter_mut() {
+            network_config
+                .deployer
+                .expand_env(&format!("networks.{}.deployer", network))?;
+            for authority in network_config.upgrade_authorities.iter_mut() {
+                *authority = expand_env(
+                    authority,
+                    &format!("networks.{}.upgrade_authority", network),
+                )?;
+            }
+            if let Some(url) = &mut network_config.url {
+                *url = expand_env(url, &format!("networks.{}.url", network))?;
+            }
+            if let Some(ws_url) = &mut network_config.ws_url {
+                *ws_url = expand_env(ws_url, &format!("networks.{}.ws_url", network))?;
+            }
+        }
+        for (program, program_config) in self.programs.iter_mut() {
+            if let Some(idl) = &mut program_config.idl {
+                idl.expand_env(&format!("programs.{}.idl", program))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads and validates a config file. The workspace root is the directory
+    /// containing it, which relative paths in the config are resolved against.
+    fn load(path: &Path) -> Result<(Self, Manifest, PathBuf)> {
+        log::info!("Using config {}", path.display());
+        let mut cfg_file = File::open(path)?;
+        let mut cfg_contents = String::new();
+        cfg_file.read_to_string(&mut cfg_contents)?;
+        let mut cfg: Config = cfg_contents
+            .parse()
+            .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        cfg.validate(path)?;
+        // Networks without their own deployer use the top-level default.
+        if let Some(deployer) = &cfg.deployer {
+            for network_config in cfg.networks.values_mut() {
+                if network_config.deployer.is_empty() {
+                    network_config.deployer = deployer.clone();
+                }
+            }
+        }
+        cfg.expand_env()
+            .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+
+        let root = fs::canonicalize(path)?
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| format_err!("{} has no parent directory", path.display()))?;
+        cfg.paths.artifacts.resolve(&root);
+        cfg.paths.program_keypairs.resolve(&root);
+        for network_config in cfg.networks.values_mut() {
+            network_config.deployer.resolve(&root);
+        }
+        for idl in cfg.programs.values_mut().filter_map(|p| p.idl.as_mut()) {
+            idl.resolve(&root);
+        }
+
+        Ok((cfg, Manifest::from_path(root.join("Cargo.toml"))?, root))
+    }
+}
+
+/// Reads the Captain.toml at `path` as written, without filling in defaults or
+/// resolving paths.
+fn read_raw(path: &Path) -> Result<toml::Value> {
+    fs::read_to_string(path)?
+        .parse()
+        .map_err(|e| format_err!("{}: {}", path.display(), e))
+}
+
+/// Looks up a dotted key such as `networks.devnet.url` in the Captain.toml at `path`.
+/// Returns `None` if it is not set.
+pub fn get_value(path: &Path, key: &str) -> Result<Option<toml::Value>> {
+    let mut value = &read_raw(path)?;
+    for part in key.split('.') {
+        value = match value.get(part) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(value.clone()))
+}
+
+/// Sets a dotted key in the Captain.toml at `path`, creating missing tables. The value
+/// keeps the type of the value it replaces. A new value is parsed as TOML, e.g. `8` or
+/// `["a", "b"]`, and taken as a string if that fails. Only the key is rewritten, so
+/// comments and the layout of the rest of the file are kept. The file is only written
+/// if the result is still a valid config.
+pub fn set_value(path: &Path, key: &str, value: &str) -> Result<()> {
+    let mut doc: toml_edit::Document = fs::read_to_string(path)?
+        .parse()
+        .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+    let parts: Vec<&str> = key.split('.').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(anyhow!("Invalid key {:?}", key));
+    }
+    if !is_known_key(&parts) {
+        return Err(anyhow!("Unknown key {}", key));
+    }
+    let (last, tables) = parts.split_last().unwrap_or((&"", &[]));
+    let mut item = doc.as_item_mut();
+    for (i, part) in tables.iter().enumerate() {
+        // Tables nested in an inline table must be inline too.
+        let new_table = if item.is_inline_table() {
+            toml_edit::value(toml_edit::InlineTable::new())
+        } else {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        };
+        item = item
+            .as_table_like_mut()
+            .ok_or_else(|| format_err!("{} is not a table", parts[..i].join(".")))?
+            .entry(part)
+            .or_insert(new_table);
+    }
+    let table = item
+        .as_table_like_mut()
+        .ok_or_else(|| format_err!("{} is not a table", tables.join(".")))?;
+    let current = match table.get(last) {
+        Some(toml_edit::Item::Value(current)) => Some(current),
+        Some(toml_edit::Item::None) | None => None,
+        Some(current) => {
+            return Err(anyhow!(
+                "Invalid value for {}: expected {}, got {}",
+                key,
+                current.type_name(),
+                value
+            ))
+        }
+    };
+    let mut new_value =
+        parse_value(value, current).map_err(|e| format_err!("Invalid value for {}: {}", key, e))?;
+    // Keeps the spacing and any comment after the value it replaces.
+    match current {
+        Some(current) => *new_value.decor_mut() = current.decor().clone(),
+        None => new_value.decor_mut().clear(),
+    }
+    // Assigned in place so that the key keeps its spacing too.
+    match table.get_mut(last) {
+        Some(item) if !item.is_none() => *item = toml_edit::Item::Value(new_value),
+        _ => {
+            table.insert(last, toml_edit::Item::Value(new_value));
+        }
+    }
+
+    let contents = doc.to_string();
+    let config: Config = contents.parse()?;
+    config.validate(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())?;
+    file.persist(path)?;
+    Ok(())
+}
+
+/// Whether `parts` of a dotted key name a field of Captain.toml, including the old
+/// names still accepted for some of them.
+fn is_known_key(parts: &[&str]) -> bool {
+    matches!(
+        parts,
+        ["default_network"
+            | "deployer"
+            | "anchor_version"
+            | "solana_version"
+            | "build_command"
+            | "version_source"
+            | "dirty_tree"
+            | "max_program_size"
+            | "exclude"
+            | "pre_deploy"
+            | "post_deploy"]
+            | ["paths", "artifacts" | "artifacts_dir" | "program_keypairs"]
+            | [
+                "networks",
+                _,
+                "deployer"
+                    | "upgrade_authority"
+                    | "url"
+                    | "rpc_url"
+                    | "ws_url"
+                    | "priority_fee"
+                    | "compute_unit_limit"
+                    | "pre_deploy"
+                    | "post_deploy"
+            ]
+            | ["programs", _, "address" | "features" | "idl" | "networks"]
+            | ["programs", _, "network_features", _]
+    )
+}
+
+/// Parses `value` as the same type as `current`, or guesses the type if there is none.
+fn parse_value(value: &str, current: Option<&toml_edit::Value>) -> Result<toml_edit::Value> {
+    use toml_edit::Value;
+    Ok(match current {
+        Some(Value::String(_)) => Value::from(value),
+        Some(Value::Integer(_)) => Value::from(value.parse::<i64>()?),
+        Some(Value::Float(_)) => Value::from(value.parse::<f64>()?),
+        Some(Value::Boolean(_)) => Value::from(value.parse::<bool>()?),
+        Some(current) => {
+            let parsed: Value = value.parse()?;
+            if parsed.type_name() != current.type_name() {
+                return Err(anyhow!("expected {}, got {}", current.type_name(), value));
+            }
+            parsed
+        }
+        None => value.parse().unwrap_or_else(|_| Value::from(value)),
+    })
+}
+
+/// Matches `name` against a pattern where `*` matches any characters and `?` matches one.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
     }
 }
 
+/// Expands `${VAR}` placeholders and a leading `~` in the value of `key`, erroring if a
+/// referenced variable is not set.
+fn expand_env(value: &str, key: &str) -> Result<String> {
+    shellexpand::full(value)
+        .map(|expanded| expanded.into_owned())
+        .map_err(|e| format_err!("`{}` references ${{{}}}, which is not set", key, e.var_name))
+}
+
 #[derive(Debug, Default, Serialize, DeserializeFromStr, Clone)]
 pub struct CaptainPath(pub PathBuf);
 
@@ -181,6 +721,23 @@ impl CaptainPath {
     pub fn as_path_buf(&self) -> PathBuf {
         self.0.clone()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.as_os_str().is_empty()
+    }
+
+    fn expand_env(&mut self, key: &str) -> Result<()> {
+        let expanded = expand_env(&self.0.to_string_lossy(), key)?;
+        self.0 = PathBuf::from(expanded);
+        Ok(())
+    }
+
+    /// Makes a relative path relative to `root` instead of the working directory.
+    fn resolve(&mut self, root: &Path) {
+        if self.0.is_relative() {
+            self.0 = root.join(self.0.strip_prefix(".").unwrap_or(&self.0));
+        }
+    }
 }
 
 impl FromStr for CaptainPath {
@@ -201,3 +758,136 @@ impl FromStr for Config {
             .map_err(|e| anyhow::format_err!("Unable to deserialize config: {}", e.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        wildcard_match(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn wildcard_match_without_wildcards() {
+        assert!(matches("foo", "foo"));
+        assert!(!matches("foo", "foobar"));
+        assert!(!matches("foo", "fo"));
+        assert!(!matches("foo", "Foo"));
+    }
+
+    #[test]
+    fn wildcard_match_empty() {
+        assert!(matches("", ""));
+        assert!(!matches("", "foo"));
+        assert!(matches("*", ""));
+        assert!(!matches("?", ""));
+        assert!(!matches("foo", ""));
+    }
+
+    #[test]
+    fn wildcard_match_leading_and_trailing_star() {
+        assert!(matches("*", "foo"));
+        assert!(matches("*_test", "foo_test"));
+        assert!(matches("*_test", "_test"));
+        assert!(!matches("*_test", "foo_test_bar"));
+        assert!(matches("test_*", "test_foo"));
+        assert!(matches("test_*", "test_"));
+        assert!(!matches("test_*", "my_test_foo"));
+        assert!(matches("*test*", "my_test_foo"));
+    }
+
+    #[test]
+    fn wildcard_match_multiple_stars() {
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("a**c", "ac"));
+        assert!(matches("a*b*c", "abcbc"));
+        assert!(!matches("a*b*c", "acb"));
+        assert!(!matches("a*b*c", "abcd"));
+        assert!(matches("*?*", "x"));
+        assert!(!matches("*??*", "x"));
+    }
+
+    const CONFIG: &str = r#"# Deployment settings
+[paths]
+artifacts = "./.captain/artifacts/"
+program_keypairs = "./.captain/program_keypairs"
+
+[networks.devnet]
+deployer   = "./deployer.json"   # the devnet deployer
+upgrade_authority = "./authority.json"
+url = "https://api.devnet.solana.com"
+
+# Programs
+[programs.foo]
+address = "HVjb325RhBW9WiNHJagnUE21C9bHKJU4hLJnD42rfQ4h"
+"#;
+
+    fn write_config() -> tempfile::NamedTempFile {
+        let file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        fs::write(file.path(), CONFIG).unwrap();
+        file
+    }
+
+    #[test]
+    fn set_value_keeps_comments_and_formatting() {
+        let file = write_config();
+        set_value(file.path(), "networks.devnet.deployer", "./other.json").unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            CONFIG.replace("\"./deployer.json\"", "\"./other.json\"")
+        );
+    }
+
+    #[test]
+    fn set_value_sets_nested_network_keys() {
+        let file = write_config();
+        set_value(file.path(), "networks.devnet.priority_fee", "5000").unwrap();
+        set_value(
+            file.path(),
+            "networks.devnet.url",
+            "https://rpc.example.com",
+        )
+        .unwrap();
+        assert_eq!(
+            get_value(file.path(), "networks.devnet.priority_fee").unwrap(),
+            Some(toml::Value::Integer(5000))
+        );
+        assert_eq!(
+            get_value(file.path(), "networks.devnet.url").unwrap(),
+            Some(toml::Value::String("https://rpc.example.com".to_string()))
+        );
+        let config: Config = fs::read_to_string(file.path()).unwrap().parse().unwrap();
+        assert_eq!(config.networks[&Network::Devnet].priority_fee, Some(5000));
+
+        // A new network also needs an upgrade authority, so it cannot be added one key
+        // at a time.
+        let before = fs::read_to_string(file.path()).unwrap();
+        assert!(set_value(file.path(), "networks.testnet.deployer", "./testnet.json").is_err());
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), before);
+    }
+
+    #[test]
+    fn set_value_keeps_the_type_of_the_old_value() {
+        let file = write_config();
+        set_value(file.path(), "networks.devnet.priority_fee", "5000").unwrap();
+        assert!(set_value(file.path(), "networks.devnet.priority_fee", "high").is_err());
+        assert!(set_value(file.path(), "networks.devnet", "x").is_err());
+    }
+
+    #[test]
+    fn set_value_rejects_unknown_keys() {
+        let file = write_config();
+        for key in [
+            "bogus",
+            "paths.bogus",
+            "networks.devnet.bogus",
+            "networks.devnet",
+            "programs.foo.bogus",
+            "networks..url",
+        ] {
+            assert!(set_value(file.path(), key, "1").is_err(), "{}", key);
+        }
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), CONFIG);
+    }
+}
@@ -0,0 +1,1321 @@
+//! Deploying and upgrading programs.
+use crate::command;
+use crate::config::Network;
+use crate::error::{AuthorityKind, CaptainError};
+use crate::keypair;
+use crate::multisig;
+use crate::output::{confirm, confirm_mainnet, output_header, Output};
+use crate::solana::BufferInfo;
+use crate::workspace;
+use crate::workspace::Hook;
+use crate::workspace::ProgramOverrides;
+use crate::workspace::Workspace;
+use anyhow::{anyhow, format_err, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Result of a deploy or upgrade, printed with `--output json`.
+#[derive(Debug, Serialize)]
+pub struct DeployOutput {
+    pub program: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub program_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deploy_version: Option<String>,
+    pub network: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// With `--multisig`, the upgrade instruction to propose.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade_instruction: Option<multisig::SerializedInstruction>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub already_deployed: bool,
+    /// Set if the on-chain program already matched, so nothing was sent.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub up_to_date: bool,
+    /// Set if the program was skipped by `--since` because it did not change.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+    /// Set if the deploy was only simulated with `--simulate`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub simulated: bool,
+    /// SHA-256 of the deployed binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_sha256: Option<String>,
+    /// Git commit the workspace was at, with a `-dirty` suffix for uncommitted changes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Set if the program was deployed with `--final`.
+    #[serde(rename = "final", skip_serializing_if = "std::ops::Not::not")]
+    pub is_final: bool,
+    pub timestamp: DateTime<Utc>,
+    pub dry_run: bool,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl DeployOutput {
+    pub fn success(
+        workspace: &Workspace,
+        program: &str,
+        buffer: Option<Pubkey>,
+        signature: Option<String>,
+    ) -> Self {
+        DeployOutput {
+            program: program.to_string(),
+            program_key: Some(workspace.program_key.to_string()),
+            deploy_version: Some(workspace.deploy_version.to_string()),
+            network: workspace.network_label(),
+            buffer: buffer.map(|b| b.to_string()),
+            signature,
+            upgrade_instruction: None,
+            already_deployed: false,
+            up_to_date: false,
+            unchanged: false,
+            simulated: false,
+            artifact_sha256: file_sha256(&workspace.artifact_paths.bin),
+            commit: workspace.commit.clone(),
+            label: workspace.label.clone(),
+            is_final: false,
+            timestamp: Utc::now(),
+            dry_run: command::is_dry_run(),
+            success: true,
+            error: None,
+        }
+    }
+
+    pub fn failure(program: &str, network: String, err: &anyhow::Error) -> Self {
+        DeployOutput {
+            program: program.to_string(),
+            program_key: None,
+            deploy_version: None,
+            network,
+            buffer: None,
+            signature: None,
+            upgrade_instruction: None,
+            already_deployed: false,
+            up_to_date: false,
+            unchanged: false,
+            simulated: false,
+            artifact_sha256: None,
+            commit: None,
+            label: None,
+            is_final: false,
+            timestamp: Utc::now(),
+            dry_run: command::is_dry_run(),
+            success: false,
+            error: Some(err.to_string()),
+        }
+    }
+
+    /// A program skipped by `--since`.
+    pub fn unchanged(program: &str, network: String) -> Self {
+        DeployOutput {
+            program: program.to_string(),
+            program_key: None,
+            deploy_version: None,
+            network,
+            buffer: None,
+            signature: None,
+            upgrade_instruction: None,
+            already_deployed: false,
+            up_to_date: false,
+            unchanged: true,
+            simulated: false,
+            artifact_sha256: None,
+            commit: None,
+            label: None,
+            is_final: false,
+            timestamp: Utc::now(),
+            dry_run: command::is_dry_run(),
+            success: true,
+            error: None,
+        }
+    }
+}
+
+/// SHA-256 of the file, or `None` if it cannot be read.
+pub fn file_sha256(path: &Path) -> Option<String> {
+    std::fs::read(path)
+        .ok()
+        .map(|data| workspace::sha256_hex(&data))
+}
+
+/// Options of `captain deploy` which apply to every program being deployed.
+pub struct DeployOptions {
+    pub version: Option<Version>,
+    pub network: Network,
+    pub output: Output,
+    pub skip_anchor_idl: bool,
+    pub idl_only: bool,
+    pub priority_fee: Option<u64>,
+    pub compute_unit_limit: Option<u32>,
+    pub yes: bool,
+    pub skip_balance_check: bool,
+    pub max_retries: u32,
+    pub config_path: Option<PathBuf>,
+    pub deployer: Option<String>,
+    pub strict: bool,
+    pub strict_anchor: bool,
+    pub max_program_size: Option<u64>,
+    pub fee_payer: Option<String>,
+    pub signers: Vec<String>,
+    pub network_url: Option<String>,
+    pub overrides: ProgramOverrides,
+    pub from_buffer: Option<Pubkey>,
+    pub simulate: bool,
+    pub verify_deploy: bool,
+    pub wait_confirmations: Option<u64>,
+    pub label: Option<String>,
+    pub is_final: bool,
+    /// Save which steps completed, and skip the ones a previous run completed.
+    pub resumable: bool,
+}
+
+/// Steps of a `captain deploy --resumable` which completed, saved so that running it
+/// again skips them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeployProgress {
+    /// SHA-256 of the binary being deployed. Progress of another binary is discarded.
+    pub artifact_sha256: Option<String>,
+    pub deployed: bool,
+    pub signature: Option<String>,
+    pub authority_set: bool,
+    pub idl_initialized: bool,
+}
+
+impl DeployProgress {
+    /// Reads the progress of deploying the binary with the given hash, or starts afresh
+    /// if there is none.
+    pub fn load(path: &Path, artifact_sha256: Option<String>) -> Result<Self> {
+        if path.exists() {
+            let progress: DeployProgress = serde_json::from_slice(&std::fs::read(path)?)
+                .map_err(|e| format_err!("could not parse {}: {}", path.display(), e))?;
+            if progress.artifact_sha256 == artifact_sha256 {
+                return Ok(progress);
+            }
+        }
+        Ok(DeployProgress {
+            artifact_sha256,
+            ..DeployProgress::default()
+        })
+    }
+
+    /// Does nothing in dry run mode, since no step actually completed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if command::is_dry_run() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .map_err(|e| format_err!("could not save {}: {}", path.display(), e))
+    }
+}
+
+/// Warns if `onchain` is none of the upgrade authorities of the network. During a
+/// rotation, any of the listed authorities is accepted.
+pub fn warn_on_authority_mismatch(workspace: &Workspace, onchain: &str) {
+    match workspace.network_config.accepted_upgrade_authorities() {
+        Ok(accepted) if !accepted.iter().any(|a| a.to_string() == onchain) => {
+            let accepted: Vec<_> = accepted.iter().map(Pubkey::to_string).collect();
+            println!();
+            println!(
+                "{}",
+                format!(
+                    "WARNING: on-chain upgrade authority {} does not match the upgrade_authority {} in Captain.toml",
+                    onchain,
+                    accepted.join(" or ")
+                )
+                .yellow()
+            );
+        }
+        Ok(_) => {}
+        Err(err) => {
+            println!();
+            println!("{}", format!("WARNING: {}", err).yellow());
+        }
+    }
+}
+
+/// Deploys a single program for `captain deploy`.
+pub fn deploy_program(program: &str, opts: &DeployOptions) -> Result<DeployOutput> {
+    let output = opts.output;
+    let mut workspace = workspace::load_with_overrides(
+        program,
+        opts.version.clone(),
+        opts.network.clone(),
+        opts.config_path.as_deref(),
+        &opts.overrides,
+    )?;
+    if let Some(deployer) = &opts.deployer {
+        workspace.override_deployer(deployer)?;
+    }
+    workspace.network_url_override = opts.network_url.clone();
+    workspace.label = opts.label.clone();
+    if opts.max_program_size.is_some() {
+        workspace.max_program_size = opts.max_program_size;
+    }
+    if let Some(fee_payer) = &opts.fee_payer {
+        workspace.set_fee_payer(fee_payer)?;
+    }
+    workspace.signers = opts.signers.clone();
+    let workspace = &workspace;
+    status!(
+        output,
+        "Deploying program {} with version {} (from {})",
+        program,
+        workspace.deploy_version,
+        workspace.version_source
+    );
+
+    status!(output, "Address: {}", workspace.program_key);
+    status!(output, "Deployer: {}", workspace.deployer_label());
+    workspace.require_cli(!opts.skip_anchor_idl)?;
+    if workspace.has_anchor() && !opts.skip_anchor_idl {
+        workspace.check_anchor_version(opts.strict_anchor)?;
+    }
+    if !opts.simulate {
+        workspace.check_dirty_tree()?;
+    }
+
+    if opts.idl_only {
+        if !workspace.has_anchor() {
+            return Err(anyhow!("--idl-only requires an Anchor workspace."));
+        }
+        if !workspace.show_program()? {
+            return Err(anyhow!(
+                "Program {} does not exist. Deploy it before initializing its IDL.",
+                workspace.program_key
+            ));
+        }
+        confirm_mainnet(program, &opts.network, opts.yes || command::is_dry_run())?;
+        init_idl(workspace, output)?;
+        if command::is_dry_run() {
+            status!(output, "Dry run complete. No transactions were sent.");
+        } else {
+            status!(output, "IDL initialization success!");
+        }
+        return Ok(DeployOutput::success(workspace, program, None, None));
+    }
+
+    // A prebuilt binary has no relation to the local sources.
+    let bin = match &opts.overrides.bin {
+        Some(_) => &workspace.program_paths.bin,
+        None => {
+            if opts.from_buffer.is_none() {
+                workspace.check_stale(program, opts.strict)?;
+            }
+            &workspace.artifact_paths.bin
+        }
+    };
+    if opts.from_buffer.is_none() {
+        workspace.check_program_size(bin)?;
+    }
+
+    let progress_path = workspace.deploy_progress_path(program);
+    let mut progress = if opts.resumable {
+        DeployProgress::load(&progress_path, file_sha256(bin))?
+    } else {
+        DeployProgress::default()
+    };
+    // Saves the progress once a step completes, if the deploy is resumable.
+    let save_progress = |progress: &DeployProgress| -> Result<()> {
+        if opts.resumable {
+            progress.save(&progress_path)?;
+        }
+        Ok(())
+    };
+    if progress.deployed {
+        status!(
+            output,
+            "Resuming deploy of {} from {}",
+            program,
+            progress_path.display()
+        );
+    }
+
+    if !progress.deployed && workspace.show_program()? {
+        status!(
+            output,
+            "On-chain size: {} bytes, local size: {} bytes",
+            workspace.fetch_program()?.data_len,
+            std::fs::metadata(bin)?.len()
+        );
+        let up_to_date = workspace.is_up_to_date(bin)?;
+        if up_to_date {
+            status!(output, "Program is already up to date.");
+        } else {
+            status!(
+                output,
+                "Program already deployed. Use `captain upgrade` if you want to upgrade the program."
+            );
+        }
+        return Ok(DeployOutput {
+            already_deployed: true,
+            up_to_date,
+            artifact_sha256: file_sha256(bin),
+            ..DeployOutput::success(workspace, program, None, None)
+        });
+    }
+
+    let buffer = opts
+        .from_buffer
+        .as_ref()
+        .map(|buffer| workspace.fetch_deployer_buffer(buffer))
+        .transpose()?;
+
+    if opts.simulate {
+        if let Some(buffer) = &buffer {
+            return simulate_deploy(workspace, program, output, buffer);
+        }
+    }
+
+    confirm_mainnet(program, &opts.network, opts.yes || command::is_dry_run())?;
+    if opts.is_final {
+        status!(
+            output,
+            "{}",
+            format!(
+                "WARNING: {} is deployed with --final and can never be upgraded or closed.",
+                program
+            )
+            .red()
+            .bold()
+        );
+    }
+
+    if !opts.skip_balance_check && !progress.deployed {
+        let program_len = match &buffer {
+            Some(buffer) => buffer.data_len,
+            None => std::fs::metadata(bin)?.len() as usize,
+        };
+        workspace.check_deployer_balance(workspace::estimate_deploy_cost(program_len)?)?;
+    }
+
+    // Only once the deploy is certain to be attempted, so that `post_deploy` follows
+    // unless it fails. A simulation does not deploy anything, so there is nothing to
+    // announce.
+    if !opts.simulate {
+        workspace.run_hook(Hook::PreDeploy, program)?;
+    }
+
+    let signature = if progress.deployed {
+        output_header(output, "Skipping deploy: the program was already deployed");
+        progress.signature.clone()
+    } else {
+        output_header(output, "Deploying program");
+
+        let source = match &buffer {
+            Some(buffer) => vec!["--buffer".into(), buffer.address.clone().into()],
+            None => vec![bin.as_os_str().to_os_string()],
+        };
+        let deploy_output = command::exec_with_progress(
+            solana_cmd!(workspace)
+                .arg("program")
+                .arg("deploy")
+                .args(source)
+                .arg("--program-id")
+                .arg(&workspace.program_paths.id)
+                .arg("--output")
+                .arg("json")
+                .arg("--max-sign-attempts")
+                .arg((opts.max_retries + 1).to_string())
+                .args(opts.is_final.then_some("--final"))
+                .args(workspace.signer_args())
+                .args(workspace.priority_fee_args(opts.priority_fee))
+                .args(workspace.compute_unit_limit_args(opts.compute_unit_limit)?),
+            opts.max_retries,
+            "Deploying",
+        )?;
+        let signature = workspace.parse_deploy_signature(&deploy_output)?;
+        progress.deployed = true;
+        progress.signature = signature.clone();
+        save_progress(&progress)?;
+        signature
+    };
+
+    if opts.is_final {
+        output_header(output, "Skipping upgrade authority: the program is final");
+    } else if progress.authority_set {
+        status!(
+            output,
+            "Upgrade authority was already set to {}",
+            workspace.network_config.upgrade_authority()
+        );
+    } else {
+        // The program does not exist yet in dry run mode, so there is nothing to compare.
+        let target_authority = workspace.network_config.upgrade_authority_pubkey()?;
+        let current_authority = if command::is_dry_run() {
+            None
+        } else {
+            Some(workspace.fetch_program()?.authority)
+        };
+        if current_authority.as_deref() == Some(target_authority.to_string().as_str()) {
+            status!(output, "Upgrade authority is already {}", target_authority);
+        } else {
+            output_header(output, "Setting upgrade authority");
+            if let Some(current_authority) = &current_authority {
+                status!(
+                    output,
+                    "Changing upgrade authority from {} to {}",
+                    current_authority,
+                    target_authority
+                );
+            }
+
+            workspace.set_upgrade_authority(None, workspace.network_config.upgrade_authority())?;
+        }
+        progress.authority_set = true;
+        save_progress(&progress)?;
+    }
+
+    workspace.show_program()?;
+
+    if workspace.has_anchor() {
+        if opts.skip_anchor_idl {
+            output_header(output, "Skipping Anchor IDL upload.");
+        } else if opts.is_final {
+            output_header(
+                output,
+                "Skipping Anchor IDL upload: initializing it requires the upgrade authority, which final programs do not have.",
+            );
+        } else if !workspace.program_paths.idl.exists() {
+            output_header(output, "Skipping Anchor IDL upload: no IDL was built.");
+        } else if progress.idl_initialized {
+            output_header(
+                output,
+                "Skipping Anchor IDL upload: the IDL was already initialized.",
+            );
+        } else {
+            init_idl(workspace, output)?;
+            progress.idl_initialized = true;
+            save_progress(&progress)?;
+        }
+    }
+
+    if let Some(confirmations) = opts.wait_confirmations {
+        wait_for_confirmations(workspace, output, signature.as_deref(), confirmations)?;
+    }
+    if opts.verify_deploy {
+        workspace.check_deployed()?;
+    }
+    workspace.record_deployment(program, opts.from_buffer, signature.clone())?;
+    if let Some(path) = workspace.save_labeled_artifact(program, bin)? {
+        status!(output, "Saved labeled binary to {}", path.display());
+    }
+    workspace.run_hook(Hook::PostDeploy, program)?;
+    if opts.resumable && progress_path.exists() {
+        std::fs::remove_file(&progress_path)?;
+    }
+
+    if command::is_dry_run() {
+        status!(output, "Dry run complete. No transactions were sent.");
+    } else {
+        status!(output, "Deployment success!");
+    }
+    if let Some(signature) = &signature {
+        status!(output, "Signature: {}", signature);
+    }
+
+    let buffer = opts.from_buffer;
+    Ok(DeployOutput {
+        artifact_sha256: file_sha256(bin),
+        is_final: opts.is_final,
+        ..DeployOutput::success(workspace, program, buffer, signature)
+    })
+}
+
+/// Simulates deploying `program` from `buffer` and prints the logs for
+/// `captain deploy --simulate`.
+pub fn simulate_deploy(
+    workspace: &Workspace,
+    program: &str,
+    output: Output,
+    buffer: &BufferInfo,
+) -> Result<DeployOutput> {
+    output_header(output, "Simulating deploy");
+    let simulation = workspace.simulate_deploy(buffer)?;
+    for line in simulation.logs.iter().flatten() {
+        status!(output, "    {}", line);
+    }
+    if let Some(units) = simulation.units_consumed {
+        status!(output, "Compute units consumed: {}", units);
+    }
+    if let Some(err) = simulation.err {
+        return Err(anyhow!("Simulation failed: {}", err));
+    }
+    status!(output, "Simulation success! No transactions were sent.");
+    Ok(DeployOutput {
+        simulated: true,
+        ..DeployOutput::success(workspace, program, buffer.address.parse().ok(), None)
+    })
+}
+
+/// Waits for the deploy or upgrade transaction to be confirmed. Older versions of the
+/// Solana CLI do not print the signature, so there is nothing to wait on.
+pub fn wait_for_confirmations(
+    workspace: &Workspace,
+    output: Output,
+    signature: Option<&str>,
+    confirmations: u64,
+) -> Result<()> {
+    if command::is_dry_run() {
+        return Ok(());
+    }
+    let signature = match signature {
+        Some(signature) => signature,
+        None => {
+            status!(
+                output,
+                "{}",
+                "The Solana CLI did not print a signature, so confirmations cannot be awaited."
+                    .yellow()
+            );
+            return Ok(());
+        }
+    };
+    status!(
+        output,
+        "Waiting for {} confirmations of {}",
+        confirmations,
+        signature
+    );
+    workspace.wait_for_confirmations(signature, confirmations)
+}
+
+/// Creates the IDL account of a newly deployed program and hands it over to the
+/// upgrade authority.
+pub fn init_idl(workspace: &Workspace, output: Output) -> Result<()> {
+    output_header(output, "Initializing IDL");
+    command::exec(
+        anchor_cmd!(workspace, "idl")
+            .arg("init")
+            .arg(workspace.program_key.to_string())
+            .arg("--filepath")
+            .arg(&workspace.program_paths.idl),
+    )?;
+
+    output_header(output, "Setting IDL authority");
+    command::exec(
+        anchor_cmd!(workspace, "idl")
+            .arg("set-authority")
+            .arg("--program-id")
+            .arg(workspace.program_key.to_string())
+            .arg("--new-authority")
+            .arg(workspace.network_config.upgrade_authority()),
+    )
+}
+
+/// Options of `captain upgrade`, `captain rollback` and `captain resume-upgrade`.
+/// [rollback] and [resume_upgrade] only use the upgrade authority, fees, retries, `yes`
+/// and, for [rollback], `skip_balance_check` and `force`.
+pub struct UpgradeOptions {
+    pub output: Output,
+    /// Not needed with `multisig`, since the multisig signs the upgrade itself.
+    pub upgrade_authority_keypair: Option<String>,
+    pub priority_fee: Option<u64>,
+    pub compute_unit_limit: Option<u32>,
+    pub max_retries: u32,
+    pub yes: bool,
+    /// Stop once the buffer is handed over to the upgrade authority.
+    pub multisig: bool,
+    pub skip_anchor_idl: bool,
+    /// Only upload the Anchor IDL, leaving the program as it is.
+    pub idl_only: bool,
+    /// Extend the program data account first if the new binary does not fit in it.
+    pub auto_extend: bool,
+    pub skip_balance_check: bool,
+    /// Upgrade even if the on-chain program is already identical.
+    pub force: bool,
+    pub strict: bool,
+    pub strict_anchor: bool,
+    pub verify_deploy: bool,
+    pub wait_confirmations: Option<u64>,
+}
+
+impl UpgradeOptions {
+    pub fn upgrade_authority_keypair(&self) -> Result<&str> {
+        self.upgrade_authority_keypair.as_deref().ok_or_else(|| {
+            format_err!(
+                "Must pass --upgrade-authority or set the UPGRADE_AUTHORITY_KEYPAIR environment variable."
+            )
+        })
+    }
+
+    /// Errors unless the upgrade authority keypair is the on-chain upgrade authority of
+    /// the program, so that the wrong keypair fails before anything is sent rather than
+    /// in the middle of the upgrade. Keypairs on devices or behind prompts are trusted.
+    pub fn check_upgrade_authority(&self, workspace: &Workspace) -> Result<()> {
+        check_upgrade_authority(workspace, self.upgrade_authority_keypair()?)?;
+        Ok(())
+    }
+}
+
+/// Errors unless the program is upgradable and the `upgrade_authority` keypair holds
+/// its upgrade authority, and returns that authority. Keypairs on devices or behind
+/// prompts are trusted.
+pub fn check_upgrade_authority(workspace: &Workspace, upgrade_authority: &str) -> Result<String> {
+    let onchain = workspace.fetch_program()?.authority;
+    if onchain == "none" {
+        return Err(anyhow!(
+            "Program {} is immutable and cannot be upgraded.",
+            workspace.program_key
+        ));
+    }
+    let signer = match keypair::pubkey(upgrade_authority)? {
+        Some(signer) => signer,
+        None => return Ok(onchain),
+    };
+    if onchain != signer.to_string() {
+        return Err(CaptainError::AuthorityMismatch {
+            kind: AuthorityKind::Upgrade,
+            account: workspace.program_key.to_string(),
+            expected: signer.to_string(),
+            actual: onchain,
+        }
+        .into());
+    }
+    Ok(onchain)
+}
+
+/// Writes `bin` into a new buffer, hands the buffer over to the upgrade authority, and
+/// upgrades the program to it. Returns the address of the buffer and the signature of
+/// the upgrade transaction.
+///
+/// The buffer keypair is saved in `.captain/buffers/` until the upgrade completes, so an
+/// interrupted upgrade of the same version can resume writing to the same buffer.
+/// Callers check the upgrade authority with [UpgradeOptions::check_upgrade_authority]
+/// before anything else is sent.
+pub fn upgrade_program(
+    workspace: &Workspace,
+    program: &str,
+    bin: &Path,
+    opts: &UpgradeOptions,
+) -> Result<(Pubkey, Option<String>)> {
+    let output = opts.output;
+    let priority_fee = opts.priority_fee;
+    let max_retries = opts.max_retries;
+
+    output_header(output, "Writing buffer");
+
+    let buffer_path = workspace.buffer_keypair_path(program);
+    let saved_kp = if buffer_path.exists() {
+        Some(
+            solana_sdk::signer::keypair::read_keypair_file(&buffer_path).map_err(|_| {
+                format_err!("could not read buffer keypair {}", buffer_path.display())
+            })?,
+        )
+    } else {
+        None
+    };
+    let buffer_kp = match saved_kp {
+        Some(kp)
+            if opts.yes
+                || confirm(&format!(
+                    "Found buffer {} from an interrupted upgrade of {} {}. Resume from it? [y/N] ",
+                    kp.pubkey(),
+                    program,
+                    workspace.deploy_version
+                ))? =>
+        {
+            status!(output, "Resuming from buffer {}", kp.pubkey());
+            kp
+        }
+        saved_kp => {
+            if let Some(kp) = saved_kp {
+                status!(
+                    output,
+                    "Abandoning buffer {}. Run `captain show-buffers --close-all` to reclaim its lamports.",
+                    kp.pubkey()
+                );
+            }
+            let kp = solana_sdk::signer::keypair::Keypair::new();
+            if !command::is_dry_run() {
+                if let Some(parent) = buffer_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                solana_sdk::signer::keypair::write_keypair_file(&kp, &buffer_path)
+                    .map_err(|_| format_err!("could not save buffer keypair"))?;
+            }
+            kp
+        }
+    };
+    let buffer_key = buffer_kp.pubkey();
+    status!(output, "Buffer Pubkey: {}", buffer_key);
+    // Once the buffer is used or closed, there is nothing left to resume.
+    let forget_buffer = || -> Result<()> {
+        if !command::is_dry_run() && buffer_path.exists() {
+            std::fs::remove_file(&buffer_path)?;
+        }
+        Ok(())
+    };
+
+    command::exec_with_progress(
+        solana_cmd!(workspace)
+            .arg("program")
+            .arg("write-buffer")
+            .arg(bin)
+            .arg("--output")
+            .arg("json")
+            .arg("--buffer")
+            .arg(&buffer_path)
+            .arg("--max-sign-attempts")
+            .arg((max_retries + 1).to_string())
+            .args(workspace.signer_args())
+            .args(workspace.priority_fee_args(priority_fee))
+            .args(workspace.compute_unit_limit_args(opts.compute_unit_limit)?),
+        max_retries,
+        "Writing buffer",
+    )?;
+
+    // Past this point the buffer holds the rent for the whole program, so close it
+    // if the upgrade does not go through.
+    let mut authority_set = false;
+    let result = (|| -> Result<Option<String>> {
+        output_header(output, "Setting buffer authority");
+
+        command::exec_with_retries(
+            solana_cmd!(workspace)
+                .arg("program")
+                .arg("set-buffer-authority")
+                .arg(buffer_key.to_string())
+                .arg("--new-buffer-authority")
+                .arg(workspace.network_config.upgrade_authority()),
+            max_retries,
+        )?;
+        authority_set = true;
+
+        if opts.multisig {
+            return Ok(None);
+        }
+        switch_to_buffer(workspace, &buffer_key, opts)
+    })();
+
+    let signature = match result {
+        Ok(signature) => signature,
+        Err(err) => {
+            output_header(output, "Closing buffer");
+            // Once handed over, only the upgrade authority can close the buffer.
+            let authority_args = if authority_set {
+                vec!["--authority", opts.upgrade_authority_keypair()?]
+            } else {
+                vec![]
+            };
+            match command::exec(
+                solana_cmd!(workspace)
+                    .arg("program")
+                    .arg("close")
+                    .arg(buffer_key.to_string())
+                    .args(authority_args),
+            ) {
+                Ok(()) => forget_buffer()?,
+                Err(close_err) => {
+                    status!(
+                        output,
+                        "{}",
+                        format!("Could not close buffer {}: {}", buffer_key, close_err).red()
+                    );
+                }
+            }
+            return Err(err);
+        }
+    };
+    forget_buffer()?;
+
+    workspace.show_program()?;
+
+    Ok((buffer_key, signature))
+}
+
+/// Upgrades the program to a buffer owned by the upgrade authority. Returns the
+/// signature of the upgrade transaction.
+pub fn switch_to_buffer(
+    workspace: &Workspace,
+    buffer_key: &Pubkey,
+    opts: &UpgradeOptions,
+) -> Result<Option<String>> {
+    output_header(
+        opts.output,
+        "Switching to new buffer (please connect your wallet)",
+    );
+
+    let deploy_output = command::exec_with_retries(
+        Command::new("solana")
+            .arg("--url")
+            .arg(workspace.network_url())
+            .arg("--commitment")
+            .arg(command::commitment().to_string())
+            .arg("--keypair")
+            .arg(opts.upgrade_authority_keypair()?)
+            .arg("program")
+            .arg("deploy")
+            .arg("--buffer")
+            .arg(buffer_key.to_string())
+            .arg("--program-id")
+            .arg(workspace.program_key.to_string())
+            .arg("--output")
+            .arg("json")
+            .arg("--max-sign-attempts")
+            .arg((opts.max_retries + 1).to_string())
+            .args(workspace.signer_args())
+            .args(workspace.priority_fee_args(opts.priority_fee))
+            .args(workspace.compute_unit_limit_args(opts.compute_unit_limit)?),
+        opts.max_retries,
+    )
+    .map_err(|err| {
+        let too_small =
+            command::command_error(&err).is_some_and(command::CommandError::is_account_too_small);
+        if too_small {
+            format_err!(
+                "{}\nThe program data account is too small for the new binary. Extend it with `solana program extend {} <bytes>`, or pass --auto-extend to `captain upgrade`.",
+                err,
+                workspace.program_key
+            )
+        } else {
+            err
+        }
+    })?;
+
+    workspace.parse_deploy_signature(&deploy_output)
+}
+
+/// Errors if the program has not been deployed yet, since only existing programs can be
+/// upgraded.
+fn require_deployed(workspace: &Workspace) -> Result<()> {
+    if !workspace.show_program()? {
+        return Err(anyhow!(
+            "Program {} does not exist. Use `captain deploy` if you want to deploy the program for the first time.",
+            workspace.program_key
+        ));
+    }
+    Ok(())
+}
+
+/// Upgrades a program to its released binary and uploads its Anchor IDL for
+/// `captain upgrade`. With `multisig`, stops once the buffer is ready and returns the
+/// upgrade instruction to propose instead.
+pub fn upgrade(
+    workspace: &Workspace,
+    program: &str,
+    opts: &UpgradeOptions,
+) -> Result<DeployOutput> {
+    let output = opts.output;
+    // A multisig signs the upgrade itself, outside of Captain.
+    if !opts.multisig {
+        opts.upgrade_authority_keypair()?;
+    }
+    status!(
+        output,
+        "Upgrading program {} with version {} (from {})",
+        program,
+        workspace.deploy_version,
+        workspace.version_source
+    );
+    status!(output, "Deployer: {}", workspace.deployer_label());
+    if opts.idl_only && !workspace.has_anchor() {
+        return Err(anyhow!("--idl-only requires an Anchor workspace."));
+    }
+    workspace.require_cli(!opts.skip_anchor_idl)?;
+    if workspace.has_anchor() && !opts.skip_anchor_idl {
+        workspace.check_anchor_version(opts.strict_anchor)?;
+    }
+    let bin = &workspace.artifact_paths.bin;
+    workspace.check_stale(program, opts.strict)?;
+    if !opts.idl_only {
+        workspace.check_program_size(bin)?;
+    }
+    workspace.check_dirty_tree()?;
+
+    require_deployed(workspace)?;
+    // Before anything is confirmed or sent, so that the wrong keypair fails early.
+    if !opts.idl_only && !opts.multisig {
+        opts.check_upgrade_authority(workspace)?;
+    }
+
+    if !opts.idl_only && !opts.force && workspace.is_up_to_date(bin)? {
+        status!(
+            output,
+            "Program is already up to date. Pass --force to upgrade anyway."
+        );
+        return Ok(DeployOutput {
+            up_to_date: true,
+            ..DeployOutput::success(workspace, program, None, None)
+        });
+    }
+
+    confirm_mainnet(
+        program,
+        &workspace.network,
+        opts.yes || command::is_dry_run(),
+    )?;
+    // Only once the upgrade is certain to be attempted, so that `post_deploy` follows
+    // unless it fails.
+    workspace.run_hook(Hook::PreDeploy, program)?;
+
+    if !opts.idl_only {
+        let local_len = std::fs::metadata(bin)?.len() as usize;
+        let onchain_len = workspace.fetch_program()?.data_len;
+        status!(
+            output,
+            "On-chain size: {} bytes, local size: {} bytes",
+            onchain_len,
+            local_len
+        );
+        if local_len > onchain_len {
+            let additional = local_len - onchain_len;
+            if opts.auto_extend {
+                output_header(output, "Extending program data account");
+                // Paid by the upgrade authority like `captain extend`, or by the deployer
+                // if a multisig holds the authority.
+                workspace.extend_program(
+                    additional,
+                    opts.upgrade_authority_keypair.as_deref(),
+                    opts.max_retries,
+                )?;
+            } else {
+                status!(
+                    output,
+                    "{}",
+                    format!(
+                        "WARNING: the new binary is {} bytes larger than the program data account. Pass --auto-extend, or run `solana program extend {} {}`.",
+                        additional, workspace.program_key, additional
+                    )
+                    .yellow()
+                );
+            }
+        }
+    }
+
+    if !opts.idl_only && !opts.skip_balance_check {
+        let program_len = std::fs::metadata(bin)?.len();
+        workspace
+            .check_deployer_balance(workspace::estimate_upgrade_cost(program_len as usize)?)?;
+    }
+
+    let upgraded = if opts.idl_only {
+        None
+    } else {
+        Some(upgrade_program(workspace, program, bin, opts)?)
+    };
+
+    if workspace.has_anchor() {
+        if opts.skip_anchor_idl {
+            output_header(output, "Skipping Anchor IDL upload.");
+        } else {
+            upgrade_idl(workspace, opts)?;
+        }
+    }
+
+    let (buffer_key, signature) = match upgraded {
+        Some(upgraded) => upgraded,
+        None => {
+            if command::is_dry_run() {
+                status!(output, "Dry run complete. No transactions were sent.");
+            } else {
+                status!(output, "IDL upgrade success!");
+            }
+            return Ok(DeployOutput::success(workspace, program, None, None));
+        }
+    };
+
+    if opts.multisig {
+        let instruction = multisig::upgrade_instruction(
+            &workspace.program_key,
+            &buffer_key,
+            &workspace.network_config.upgrade_authority_pubkey()?,
+            &workspace.deployer_pubkey()?,
+        );
+        status!(
+            output,
+            "Buffer {} is ready. Propose this instruction to the multisig to upgrade the program:",
+            buffer_key
+        );
+        if !output.is_json() {
+            println!("{}", instruction);
+        }
+        return Ok(DeployOutput {
+            upgrade_instruction: Some(instruction),
+            ..DeployOutput::success(workspace, program, Some(buffer_key), None)
+        });
+    }
+
+    if let Some(confirmations) = opts.wait_confirmations {
+        wait_for_confirmations(workspace, output, signature.as_deref(), confirmations)?;
+    }
+    if opts.verify_deploy {
+        workspace.check_deployed()?;
+    }
+    workspace.record_deployment(program, Some(buffer_key), signature.clone())?;
+    workspace.run_hook(Hook::PostDeploy, program)?;
+
+    if command::is_dry_run() {
+        status!(output, "Dry run complete. No transactions were sent.");
+    } else {
+        status!(output, "Deployment success!");
+    }
+    if let Some(signature) = &signature {
+        status!(output, "Signature: {}", signature);
+    }
+    Ok(DeployOutput::success(
+        workspace,
+        program,
+        Some(buffer_key),
+        signature,
+    ))
+}
+
+/// Writes the local IDL into a buffer and switches the IDL account of the program to
+/// it, or prints the instruction to propose with `multisig`.
+fn upgrade_idl(workspace: &Workspace, opts: &UpgradeOptions) -> Result<()> {
+    let output = opts.output;
+    output_header(output, "Uploading new IDL");
+    let idl_output = command::exec_capture(
+        anchor_cmd!(workspace, "idl")
+            .arg("write-buffer")
+            .arg(workspace.program_key.to_string())
+            .arg("--filepath")
+            .arg(&workspace.program_paths.idl),
+    )?;
+    let idl_buffer = if command::is_dry_run() {
+        "<IDL_BUFFER>".to_string()
+    } else {
+        workspace::parse_idl_buffer(&idl_output)?.to_string()
+    };
+    status!(output, "IDL Buffer Pubkey: {}", idl_buffer);
+
+    // The IDL buffer is created with the deployer as its authority, but `set-buffer`
+    // requires it to match the authority of the IDL account.
+    let idl_authority = workspace.network_config.upgrade_authority_pubkey()?;
+    if workspace.deployer_pubkey()? != idl_authority {
+        output_header(output, "Setting IDL buffer authority");
+        command::exec(
+            anchor_cmd!(workspace, "idl")
+                .arg("set-authority")
+                .arg("--program-id")
+                .arg(workspace.program_key.to_string())
+                .arg("--address")
+                .arg(&idl_buffer)
+                .arg("--new-authority")
+                .arg(idl_authority.to_string()),
+        )?;
+    }
+
+    match opts
+        .upgrade_authority_keypair
+        .as_ref()
+        .filter(|_| !opts.multisig)
+    {
+        Some(upgrade_authority_keypair) => {
+            output_header(output, "Switching to new IDL");
+            command::exec(
+                anchor_cmd!(workspace, "idl", upgrade_authority_keypair)
+                    .arg("set-buffer")
+                    .arg(workspace.program_key.to_string())
+                    .arg("--buffer")
+                    .arg(&idl_buffer),
+            )
+        }
+        None => {
+            status!(
+                output,
+                "Propose `anchor idl set-buffer {} --buffer {}` to the multisig to switch to the new IDL.",
+                workspace.program_key,
+                idl_buffer
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Upgrades a program back to the released binary of `workspace.deploy_version` for
+/// `captain rollback`. Unlike [upgrade], the IDL is left as it is.
+pub fn rollback(
+    workspace: &Workspace,
+    program: &str,
+    opts: &UpgradeOptions,
+) -> Result<DeployOutput> {
+    let output = opts.output;
+    opts.upgrade_authority_keypair()?;
+    workspace.require_cli(false)?;
+    status!(
+        output,
+        "Rolling back program {} to version {}",
+        program,
+        workspace.deploy_version
+    );
+    workspace.check_dirty_tree()?;
+
+    require_deployed(workspace)?;
+    opts.check_upgrade_authority(workspace)?;
+
+    let bin = &workspace.artifact_paths.bin;
+    if !opts.force && workspace.is_up_to_date(bin)? {
+        status!(
+            output,
+            "Program is already up to date. Pass --force to upgrade anyway."
+        );
+        return Ok(DeployOutput {
+            up_to_date: true,
+            ..DeployOutput::success(workspace, program, None, None)
+        });
+    }
+
+    confirm_mainnet(
+        program,
+        &workspace.network,
+        opts.yes || command::is_dry_run(),
+    )?;
+    workspace.run_hook(Hook::PreDeploy, program)?;
+
+    if !opts.skip_balance_check {
+        let program_len = std::fs::metadata(bin)?.len();
+        workspace
+            .check_deployer_balance(workspace::estimate_upgrade_cost(program_len as usize)?)?;
+    }
+
+    let (buffer_key, signature) = upgrade_program(workspace, program, bin, opts)?;
+
+    workspace.record_deployment(program, Some(buffer_key), signature.clone())?;
+    workspace.run_hook(Hook::PostDeploy, program)?;
+
+    if command::is_dry_run() {
+        status!(output, "Dry run complete. No transactions were sent.");
+    } else {
+        status!(output, "Rollback success!");
+    }
+    if let Some(signature) = &signature {
+        status!(output, "Signature: {}", signature);
+    }
+    Ok(DeployOutput::success(
+        workspace,
+        program,
+        Some(buffer_key),
+        signature,
+    ))
+}
+
+/// Finishes an interrupted upgrade for `captain resume-upgrade` by switching the
+/// program to `buffer`, which already holds the new binary and belongs to the upgrade
+/// authority.
+pub fn resume_upgrade(
+    workspace: &Workspace,
+    program: &str,
+    buffer: &Pubkey,
+    opts: &UpgradeOptions,
+) -> Result<DeployOutput> {
+    let output = opts.output;
+    opts.upgrade_authority_keypair()?;
+    workspace.require_cli(false)?;
+    status!(
+        output,
+        "Resuming upgrade of program {} to version {} from buffer {}",
+        program,
+        workspace.deploy_version,
+        buffer
+    );
+
+    require_deployed(workspace)?;
+    opts.check_upgrade_authority(workspace)?;
+    confirm_mainnet(
+        program,
+        &workspace.network,
+        opts.yes || command::is_dry_run(),
+    )?;
+
+    let signature = switch_to_buffer(workspace, buffer, opts)?;
+
+    // The saved keypair of the buffer is no longer needed.
+    let buffer_path = workspace.buffer_keypair_path(program);
+    if !command::is_dry_run()
+        && solana_sdk::signer::keypair::read_keypair_file(&buffer_path)
+            .map(|kp| kp.pubkey() == *buffer)
+            .unwrap_or(false)
+    {
+        std::fs::remove_file(&buffer_path)?;
+    }
+
+    workspace.show_program()?;
+    workspace.record_deployment(program, Some(*buffer), signature.clone())?;
+
+    if command::is_dry_run() {
+        status!(output, "Dry run complete. No transactions were sent.");
+    } else {
+        status!(output, "Deployment success!");
+    }
+    if let Some(signature) = &signature {
+        status!(output, "Signature: {}", signature);
+    }
+    Ok(DeployOutput::success(
+        workspace,
+        program,
+        Some(*buffer),
+        signature,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deploy_progress_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deploys").join("foo.json");
+        let mut progress = DeployProgress::load(&path, Some("abc".to_string())).unwrap();
+        assert!(!progress.deployed);
+        progress.deployed = true;
+        progress.signature = Some("5sig".to_string());
+        progress.authority_set = true;
+        progress.save(&path).unwrap();
+
+        let loaded = DeployProgress::load(&path, Some("abc".to_string())).unwrap();
+        assert_eq!(loaded.artifact_sha256.as_deref(), Some("abc"));
+        assert!(loaded.deployed);
+        assert_eq!(loaded.signature.as_deref(), Some("5sig"));
+        assert!(loaded.authority_set);
+        assert!(!loaded.idl_initialized);
+    }
+
+    #[test]
+    fn deploy_progress_ignores_other_binaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("foo.json");
+        let progress = DeployProgress {
+            artifact_sha256: Some("old".to_string()),
+            deployed: true,
+            signature: Some("5sig".to_string()),
+            authority_set: true,
+            idl_initialized: true,
+        };
+        progress.save(&path).unwrap();
+
+        let loaded = DeployProgress::load(&path, Some("new".to_string())).unwrap();
+        assert_eq!(loaded.artifact_sha256.as_deref(), Some("new"));
+        assert!(!loaded.deployed);
+        assert_eq!(loaded.signature, None);
+        assert!(!loaded.authority_set);
+        assert!(!loaded.idl_initialized);
+        assert!(!DeployProgress::load(&path, None).unwrap().deployed);
+    }
+
+    #[test]
+    fn deploy_progress_rejects_corrupt_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("foo.json");
+        std::fs::write(&path, "not json").unwrap();
+        assert!(DeployProgress::load(&path, None).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Log of every deployment made from the workspace, kept in `.captain/deployments.json`.
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    pub timestamp: DateTime<Utc>,
+    pub program: String,
+    pub version: String,
+    pub network: String,
+    pub program_key: String,
+    /// Buffer the program was upgraded from, if this was an upgrade.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buffer: Option<String>,
+    /// Signature of the deploy or upgrade transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Git commit the workspace was at, with a `-dirty` suffix if it had uncommitted
+    /// changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Label passed with `deploy --label`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Parses a deployment label. Labels end up in file names, so only ASCII letters,
+/// digits, `-`, `_` and `.` are allowed.
+pub fn parse_label(label: &str) -> Result<String> {
+    let valid_chars = label
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if label.is_empty() || label.len() > 64 || label.starts_with('.') || !valid_chars {
+        return Err(anyhow::format_err!(
+            "invalid label {:?}: use 1 to 64 letters, digits, '-', '_' or '.', not starting with '.'",
+            label
+        ));
+    }
+    Ok(label.to_string())
+}
+
+pub fn log_path(root: &Path) -> PathBuf {
+    root.join(".captain").join("deployments.json")
+}
+
+/// Reads the deployment log. Returns an empty log if there have been no deployments.
+pub fn read(root: &Path) -> Result<Vec<Deployment>> {
+    let path = log_path(root);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = fs::read_to_string(&path)?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow::format_err!("Unable to parse {}: {}", path.display(), e))
+}
+
+/// Labels of the recorded deployments of a version of a program, without duplicates.
+pub fn labels(root: &Path, program: &str, version: &str) -> Result<Vec<String>> {
+    let mut labels = vec![];
+    for deployment in read(root)? {
+        if deployment.program != program || deployment.version != version {
+            continue;
+        }
+        if let Some(label) = deployment.label {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+    }
+    Ok(labels)
+}
+
+/// Appends a deployment to the log. The log is rewritten to a temporary file which
+/// then replaces it, so an interrupted write cannot corrupt it.
+pub fn append(root: &Path, deployment: Deployment) -> Result<()> {
+    let mut deployments = read(root)?;
+    deployments.push(deployment);
+
+    let path = log_path(root);
+    let dir = path.parent().unwrap_or(root);
+    fs::create_dir_all(dir)?;
+    let mut file = NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut file, &deployments)?;
+    file.write_all(b"\n")?;
+    file.persist(&path)?;
+    Ok(())
+}
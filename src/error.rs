@@ -0,0 +1,130 @@
+//! Categorized errors for the failures users most often need to act on. They travel
+//! through `anyhow` like any other error and are recovered with `downcast_ref` where
+//! their category matters, such as when printing them.
+use crate::command::CommandError;
+use std::path::PathBuf;
+use strum_macros::{Display, IntoStaticStr};
+use thiserror::Error;
+
+#[derive(Debug, Error, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum CaptainError {
+    #[error("Cargo.toml and Captain.toml not found")]
+    ConfigNotFound,
+
+    #[error("Program {} path {} does not exist{}", .kind, .path.display(), similar_suffix(.similar))]
+    ProgramNotFound {
+        /// What is missing: the `manifest`, `bin` or `idl` of the program.
+        kind: &'static str,
+        path: PathBuf,
+        /// Built programs with a similar name.
+        similar: Vec<String>,
+    },
+
+    #[error("{payer} has {balance} SOL, need ~{required} SOL")]
+    BalanceInsufficient {
+        balance: f64,
+        required: f64,
+        /// Who pays, e.g. `deployer <path>`.
+        payer: String,
+        /// Network to suggest airdropping to the deployer on, if it is not mainnet.
+        airdrop_network: Option<String>,
+    },
+
+    #[error(transparent)]
+    CommandFailed(#[from] CommandError),
+
+    #[error("{} is {expected}, but the on-chain {kind} of {account} is {actual}", .kind.signer())]
+    AuthorityMismatch {
+        kind: AuthorityKind,
+        account: String,
+        /// Pubkey of the keypair Captain would sign with.
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Which authority of an account did not match.
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+pub enum AuthorityKind {
+    #[strum(serialize = "buffer authority")]
+    Buffer,
+    #[strum(serialize = "upgrade authority")]
+    Upgrade,
+}
+
+impl AuthorityKind {
+    /// The keypair which is expected to hold the authority.
+    fn signer(self) -> &'static str {
+        match self {
+            AuthorityKind::Buffer => "The deployer",
+            AuthorityKind::Upgrade => "The upgrade authority keypair",
+        }
+    }
+}
+
+impl CaptainError {
+    /// Category of the error, as reported in JSON output.
+    pub fn kind(&self) -> &'static str {
+        self.into()
+    }
+
+    /// What the user can do about the error.
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            CaptainError::ConfigNotFound => Some(
+                "Run `captain init` at the Cargo workspace root, or pass --config.".to_string(),
+            ),
+            CaptainError::ProgramNotFound { similar, .. } if !similar.is_empty() => {
+                Some("Pass the exact name of one of the similar programs.".to_string())
+            }
+            CaptainError::ProgramNotFound { kind: "bin", .. } => {
+                Some("Run `captain build` first.".to_string())
+            }
+            CaptainError::ProgramNotFound {
+                kind: "manifest", ..
+            } => Some("Run `captain programs` to list the programs of the workspace.".to_string()),
+            CaptainError::ProgramNotFound { .. } => None,
+            CaptainError::BalanceInsufficient {
+                airdrop_network, ..
+            } => Some(match airdrop_network {
+                Some(network) => format!(
+                    "Run `captain fund --network {}` to airdrop SOL to it, or pass --skip-balance-check.",
+                    network
+                ),
+                None => "Fund it or pass --skip-balance-check.".to_string(),
+            }),
+            CaptainError::CommandFailed(_) => {
+                Some("Pass -vv to see every command Captain runs.".to_string())
+            }
+            CaptainError::AuthorityMismatch {
+                kind: AuthorityKind::Buffer,
+                account,
+                expected,
+                ..
+            } => Some(format!(
+                "Transfer it with `solana program set-buffer-authority {} --new-buffer-authority {}`.",
+                account, expected
+            )),
+            CaptainError::AuthorityMismatch {
+                kind: AuthorityKind::Upgrade,
+                actual,
+                ..
+            } => Some(format!(
+                "Pass the keypair of {} with --upgrade-authority or UPGRADE_AUTHORITY_KEYPAIR.",
+                actual
+            )),
+        }
+    }
+}
+
+fn similar_suffix(similar: &[String]) -> String {
+    if similar.is_empty() {
+        String::new()
+    } else {
+        format!(
+            ", but these programs in target/deploy are similar: {}",
+            similar.join(", ")
+        )
+    }
+}
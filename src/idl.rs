@@ -0,0 +1,131 @@
+//! Comparison of Anchor IDLs.
+use colored::*;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Sections of an IDL whose entries are compared by name.
+const SECTIONS: &[&str] = &["instructions", "accounts", "types", "events", "errors"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// An entry of an IDL section which differs between two IDLs.
+#[derive(Debug)]
+pub struct IdlChange {
+    pub kind: ChangeKind,
+    /// Section of the IDL, e.g. `instructions`.
+    pub section: &'static str,
+    pub name: String,
+}
+
+impl fmt::Display for IdlChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = format!("{} {}", self.section, self.name);
+        match self.kind {
+            ChangeKind::Added => write!(f, "{}", format!("+ {}", line).green()),
+            ChangeKind::Removed => write!(f, "{}", format!("- {}", line).red()),
+            ChangeKind::Changed => write!(f, "{}", format!("~ {}", line).yellow()),
+        }
+    }
+}
+
+/// Entries of a section, keyed by name. Entries without a name are ignored.
+fn entries<'a>(idl: &'a Value, section: &str) -> BTreeMap<&'a str, &'a Value> {
+    idl.get(section)
+        .and_then(Value::as_array)
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| Some((entry.get("name")?.as_str()?, entry)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lists the entries which were added, removed or changed going from `old` to `new`.
+pub fn diff(old: &Value, new: &Value) -> Vec<IdlChange> {
+    let mut changes = vec![];
+    for section in SECTIONS {
+        let old_entries = entries(old, section);
+        let new_entries = entries(new, section);
+        for (name, old_entry) in &old_entries {
+            let kind = match new_entries.get(name) {
+                None => ChangeKind::Removed,
+                Some(new_entry) if new_entry != old_entry => ChangeKind::Changed,
+                Some(_) => continue,
+            };
+            changes.push(IdlChange {
+                kind,
+                section,
+                name: name.to_string(),
+            });
+        }
+        for name in new_entries.keys() {
+            if !old_entries.contains_key(name) {
+                changes.push(IdlChange {
+                    kind: ChangeKind::Added,
+                    section,
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = include_str!("../tests/fixtures/idl/old.json");
+    const NEW: &str = include_str!("../tests/fixtures/idl/new.json");
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn summary(changes: &[IdlChange]) -> Vec<(ChangeKind, &str, &str)> {
+        changes
+            .iter()
+            .map(|change| (change.kind, change.section, change.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_of_fixtures() {
+        let changes = diff(&parse(OLD), &parse(NEW));
+        assert_eq!(
+            summary(&changes),
+            vec![
+                (ChangeKind::Removed, "instructions", "close"),
+                (ChangeKind::Changed, "instructions", "deposit"),
+                (ChangeKind::Added, "instructions", "withdraw"),
+                (ChangeKind::Added, "events", "Withdrawn"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_ignores_version_and_identical_idls() {
+        let old = parse(OLD);
+        assert!(diff(&old, &old).is_empty());
+        let mut bumped = old.clone();
+        bumped["version"] = Value::from("9.9.9");
+        assert!(diff(&old, &bumped).is_empty());
+    }
+
+    #[test]
+    fn diff_against_empty_idl() {
+        let old = parse(OLD);
+        let empty = serde_json::json!({});
+        assert!(diff(&old, &empty)
+            .iter()
+            .all(|change| change.kind == ChangeKind::Removed));
+        assert_eq!(diff(&empty, &old).len(), 5);
+    }
+}
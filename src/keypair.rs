@@ -0,0 +1,150 @@
+//! Keypair arguments, which may be file paths or any keypair URL the Solana CLI accepts.
+use anyhow::{anyhow, format_err, Result};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Keypair sources which cannot be checked without user interaction.
+const INTERACTIVE_PREFIXES: &[&str] = &["usb://", "prompt://", "stdin", "ASK"];
+
+/// Returns the path of a keypair file, or `None` if the keypair is read from a device
+/// or prompt.
+pub fn file_path(keypair: &str) -> Option<PathBuf> {
+    if INTERACTIVE_PREFIXES
+        .iter()
+        .any(|prefix| keypair.starts_with(prefix))
+    {
+        return None;
+    }
+    let path = keypair.strip_prefix("file://").unwrap_or(keypair);
+    Some(PathBuf::from(shellexpand::tilde(path).to_string()))
+}
+
+/// Pubkey of the keypair, or `None` if it is read from a device or prompt.
+pub fn pubkey(keypair: &str) -> Result<Option<Pubkey>> {
+    let path = match file_path(keypair) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let keypair = solana_sdk::signer::keypair::read_keypair_file(&path)
+        .map_err(|_| format_err!("could not read keypair {}", path.display()))?;
+    Ok(Some(keypair.pubkey()))
+}
+
+/// Schemes of keypair URLs the Solana CLI can sign with.
+const SIGNER_SCHEMES: &[&str] = &["usb", "prompt", "file"];
+
+/// Parses an additional signer: a keypair URL the Solana CLI accepts, such as
+/// `usb://ledger?key=1`, a keypair file, or a `PUBKEY=SIGNATURE` pair signed
+/// elsewhere.
+pub fn parse_signer(signer: &str) -> Result<String> {
+    if let Some((scheme, rest)) = signer.split_once("://") {
+        if !SIGNER_SCHEMES.contains(&scheme) {
+            return Err(anyhow!(
+                "unsupported signer {}. Expected a keypair file, a {}:// URL or PUBKEY=SIGNATURE.",
+                signer,
+                SIGNER_SCHEMES.join("://, ")
+            ));
+        }
+        if rest.is_empty() && scheme != "prompt" {
+            return Err(anyhow!("signer {} is missing its location", signer));
+        }
+    } else if let Some((pubkey, signature)) = signer
+        .split_once('=')
+        // A keypair file may have a `=` in its name.
+        .filter(|_| !Path::new(signer).exists())
+    {
+        pubkey
+            .parse::<Pubkey>()
+            .map_err(|_| format_err!("invalid signer pubkey {}", pubkey))?;
+        signature
+            .parse::<Signature>()
+            .map_err(|_| format_err!("invalid signature {} of signer {}", signature, pubkey))?;
+        return Ok(signer.to_string());
+    }
+    validate(signer)?;
+    Ok(signer.to_string())
+}
+
+/// Errors if the keypair is a file which does not exist or does not contain a keypair.
+pub fn validate(keypair: &str) -> Result<()> {
+    if let Some(path) = file_path(keypair) {
+        if !path.exists() {
+            return Err(anyhow!("Keypair {} does not exist", path.display()));
+        }
+        solana_sdk::signer::keypair::read_keypair_file(&path)
+            .map_err(|_| format_err!("could not read keypair {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Writes the keypair JSON in the environment variable `var` to a temporary file, so
+/// that it can be passed to the Solana CLI without keeping the secret at a known
+/// path. The file is deleted when the returned handle is dropped.
+pub fn from_env(var: &str) -> Result<NamedTempFile> {
+    let contents =
+        std::env::var(var).map_err(|_| format_err!("environment variable {} is not set", var))?;
+    let bytes: Vec<u8> = serde_json::from_str(contents.trim())
+        .map_err(|_| format_err!("{} does not contain a keypair JSON array", var))?;
+    solana_sdk::signer::keypair::Keypair::from_bytes(&bytes)
+        .map_err(|_| format_err!("{} does not contain a valid keypair", var))?;
+    let mut file = NamedTempFile::new()?;
+    file.write_all(contents.trim().as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signer::keypair::{write_keypair_file, Keypair};
+
+    #[test]
+    fn parse_signer_urls() {
+        for signer in [
+            "usb://ledger",
+            "usb://ledger?key=1",
+            "prompt://",
+            "prompt://?key=0/0",
+        ] {
+            assert_eq!(parse_signer(signer).unwrap(), signer);
+        }
+        assert!(parse_signer("http://example.com").is_err());
+        assert!(parse_signer("stdin://").is_err());
+    }
+
+    #[test]
+    fn parse_signer_empty_location() {
+        assert!(parse_signer("usb://").is_err());
+        assert!(parse_signer("file://").is_err());
+        assert!(parse_signer("prompt://").is_ok());
+    }
+
+    #[test]
+    fn parse_signer_pubkey_signature_pairs() {
+        let pubkey = Pubkey::new_unique();
+        let signature = Signature::new(&[1; 64]);
+        let signer = format!("{}={}", pubkey, signature);
+        assert_eq!(parse_signer(&signer).unwrap(), signer);
+        assert!(parse_signer(&format!("notapubkey={}", signature)).is_err());
+        assert!(parse_signer(&format!("{}=notasignature", pubkey)).is_err());
+        assert!(parse_signer(&format!("{}=", pubkey)).is_err());
+    }
+
+    #[test]
+    fn parse_signer_keypair_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key=1.json");
+        write_keypair_file(&Keypair::new(), &path).unwrap();
+        let path = path.to_string_lossy().to_string();
+        assert_eq!(parse_signer(&path).unwrap(), path);
+        assert_eq!(
+            parse_signer(&format!("file://{}", path)).unwrap(),
+            format!("file://{}", path)
+        );
+        let missing = dir.path().join("missing.json");
+        assert!(parse_signer(&missing.to_string_lossy()).is_err());
+    }
+}
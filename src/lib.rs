@@ -0,0 +1,19 @@
+//! The deploy, upgrade and workspace logic behind the `captain` CLI, for tools which
+//! embed Captain instead of running it.
+#[macro_use]
+mod macros;
+
+pub mod anchor;
+pub mod build;
+pub mod command;
+pub mod config;
+pub mod deploy;
+pub mod deployments;
+pub mod error;
+pub mod idl;
+pub mod keypair;
+pub mod multisig;
+pub mod output;
+pub mod rpc;
+pub mod solana;
+pub mod workspace;
@@ -1,20 +1,40 @@
+#[macro_export]
 macro_rules! solana_cmd {
     ($workspace:expr) => {
         std::process::Command::new("solana")
             .arg("--url")
             .arg(&$workspace.network_url())
+            .arg("--commitment")
+            .arg($crate::command::commitment().to_string())
             .arg("--keypair")
             .arg(&$workspace.deployer_path)
     };
 }
 
+#[macro_export]
 macro_rules! anchor_cmd {
     ($workspace:expr, $cmd:expr) => {
+        $crate::anchor_cmd!($workspace, $cmd, &$workspace.deployer_path)
+    };
+    ($workspace:expr, $cmd:expr, $wallet:expr) => {
         std::process::Command::new("anchor")
             .arg($cmd)
             .arg("--provider.cluster")
-            .arg(&$workspace.network.to_string())
+            .arg(&$workspace.network_url())
             .arg("--provider.wallet")
-            .arg(&$workspace.deployer_path)
+            .arg($wallet)
+    };
+}
+
+/// Prints a status line. With `--output json`, stdout is reserved for the final
+/// result, so status lines are written to stderr instead.
+#[macro_export]
+macro_rules! status {
+    ($output:expr, $($arg:tt)*) => {
+        if $output.is_json() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
     };
 }
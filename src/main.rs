@@ -1,80 +1,736 @@
 //! Captain entrypoint
-#[macro_use]
-mod macros;
-
-mod command;
-mod config;
-mod workspace;
-
-use crate::config::CaptainPath;
-use crate::config::Config;
-use crate::config::Network;
-use crate::config::NetworkConfig;
 use anyhow::{anyhow, format_err, Result};
-use clap::{crate_authors, crate_description, crate_version, AppSettings, Clap};
+use captain::build::{build, build_parallel};
+use captain::config::CaptainPath;
+use captain::config::Config;
+use captain::config::Network;
+use captain::config::NetworkConfig;
+use captain::config::ProgramConfig;
+use captain::deploy::{
+    check_upgrade_authority, deploy_program, warn_on_authority_mismatch, DeployOptions,
+    DeployOutput, UpgradeOptions,
+};
+use captain::output::{
+    confirm, confirm_mainnet, output_header, write_result, Output, OutputFormat,
+};
+use captain::rpc::Commitment;
+use captain::workspace::ProgramIdOverride;
+use captain::workspace::ProgramOverrides;
+use captain::{
+    anchor, command, config, deploy, deployments, error, idl, keypair, rpc, status, workspace,
+};
+use clap::{crate_authors, crate_description, crate_version, AppSettings, Clap, IntoApp};
+use clap_generate::generate;
+use clap_generate::generators::{Bash, Elvish, Fish, PowerShell, Zsh};
 use colored::*;
 use semver::Version;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signer;
-use std::env;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use strum::VariantNames;
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
 use tempfile::NamedTempFile;
 
+/// Shells `captain completions` can generate a completion script for.
+#[derive(Clone, Copy, Debug, Display, EnumString, EnumVariantNames, IntoStaticStr, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Elvish,
+}
+
 #[derive(Debug, Clap)]
 pub enum SubCommand {
     #[clap(about = "Initializes a new Captain workspace.")]
-    Init,
+    Init {
+        #[clap(long)]
+        #[clap(
+            about = "Populates programs and upgrade authorities from the Anchor.toml of the workspace."
+        )]
+        anchor: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Also generates a keypair for every program under programs/ and declares its address."
+        )]
+        keys: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Replaces an existing Captain.toml, backing it up to Captain.toml.bak. Existing deployer keypairs are kept."
+        )]
+        force: bool,
+    },
+    #[clap(about = "Validates Captain.toml without making any network calls.")]
+    Check,
+    #[clap(
+        about = "Checks the installed tools, Captain.toml, the deployer, the RPC node and the built programs."
+    )]
+    Doctor {
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to check. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+    },
+    #[clap(
+        about = "Prints the deployer, upgrade authority and RPC URL of each network, and the address of each program."
+    )]
+    Whoami {
+        #[clap(short, long)]
+        #[clap(about = "Only prints this network. Defaults to every network in Captain.toml.")]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+    },
+    #[clap(about = "Prints a shell completion script to stdout.")]
+    Completions {
+        #[clap(possible_values = Shell::VARIANTS)]
+        shell: Shell,
+    },
     #[clap(about = "Builds all programs. (Uses Anchor)")]
-    Build,
-    #[clap(about = "Request an airdrop.")]
-    Airdrop { amount: u64 },
+    Build {
+        #[clap(short, long)]
+        #[clap(about = "Only builds the program in programs/<program>.")]
+        program: Option<String>,
+        #[clap(long)]
+        #[clap(
+            about = "Builds inside the verifiable build docker image instead of with the host toolchain."
+        )]
+        verifiable: bool,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to build for. Enables the network_features of programs in Captain.toml."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(short, long, default_value = "1")]
+        #[clap(
+            about = "Number of programs to build at once. Only applies to cargo builds outside of Anchor without --verifiable."
+        )]
+        jobs: usize,
+    },
+    #[clap(about = "Reads or changes a value in Captain.toml.")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    #[clap(about = "Removes build output and saved upgrade buffers.")]
+    Clean {
+        #[clap(long)]
+        #[clap(about = "Also removes every released artifact in the artifactory.")]
+        artifacts: bool,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt.")]
+        yes: bool,
+    },
+    #[clap(about = "Airdrops SOL to the deployer of a test network.")]
+    #[clap(alias = "airdrop")]
+    Fund {
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to fund the deployer on. Mainnet is not allowed. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(short, long, default_value = "1")]
+        #[clap(about = "Amount of SOL to request.")]
+        amount: f64,
+        #[clap(long, default_value = "3")]
+        #[clap(about = "Times to retry airdrops that are rate limited or time out.")]
+        max_retries: u32,
+    },
+    #[clap(
+        about = "Starts solana-test-validator if it is not running, funds the deployer and deploys programs to it."
+    )]
+    Localnet {
+        #[clap(short, long, required_unless_present = "all")]
+        #[clap(about = "Name of the program to deploy. May be given multiple times.")]
+        program: Vec<String>,
+        #[clap(long, conflicts_with = "program")]
+        #[clap(about = "Deploy every program in target/deploy.")]
+        all: bool,
+        #[clap(long)]
+        #[clap(about = "Stops the validator after deploying, if Captain started it.")]
+        teardown: bool,
+        #[clap(long)]
+        #[clap(about = "Starts the validator with an empty ledger.")]
+        reset: bool,
+        #[clap(long)]
+        #[clap(about = "Skips uploading the Anchor IDL.")]
+        skip_anchor_idl: bool,
+        #[clap(long, default_value = "60")]
+        #[clap(about = "Seconds to wait for the validator to become healthy.")]
+        startup_timeout: u64,
+    },
     #[clap(about = "Lists all available programs.")]
     Programs,
+    #[clap(about = "Lists the released versions of a program in the artifactory.")]
+    ListVersions {
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+    },
     #[clap(about = "Releases a program into the artifactory.")]
     Release {
         #[clap(short, long)]
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
+        #[clap(long)]
+        #[clap(about = "Fail instead of warning if the program binary is older than its source.")]
+        strict: bool,
     },
     #[clap(about = "Deploys a program.")]
     Deploy {
         #[clap(short, long)]
-        #[clap(about = "Version of the program to deploy. Must be in the artifactory.")]
-        version: Version,
+        #[clap(
+            about = "Version of the program to deploy. Must be in the artifactory. Defaults to the version in its Cargo.toml."
+        )]
+        version: Option<Version>,
+        #[clap(short, long, required_unless_present_any = &["all", "since"])]
+        #[clap(
+            about = "Name of the program in target/deploy/<id>.so. May be given multiple times."
+        )]
+        program: Vec<String>,
+        #[clap(long, conflicts_with = "program")]
+        #[clap(about = "Deploy every program in target/deploy.")]
+        all: bool,
+        #[clap(long, conflicts_with_all = &["bin", "from-buffer"])]
+        #[clap(
+            about = "Only deploys the programs whose package changed since this git ref. Deploys every changed program in target/deploy unless --program is given."
+        )]
+        since: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Stop at the first program that fails to deploy.")]
+        fail_fast: bool,
+        #[clap(long, parse(try_from_str = deployments::parse_label))]
+        #[clap(
+            about = "Label recorded in the deployment log and in the name of a copy of the deployed binary, e.g. hotfix."
+        )]
+        label: Option<String>,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to deploy to. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(short, long, alias = "skip-idl")]
+        #[clap(about = "Skip all Anchor IDL steps.")]
+        skip_anchor_idl: bool,
+        #[clap(long, conflicts_with = "skip-anchor-idl")]
+        #[clap(about = "Only run the Anchor IDL steps, leaving the program as it is.")]
+        idl_only: bool,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit price in micro-lamports. Overrides the network's priority_fee in Captain.toml."
+        )]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit limit of deploy transactions, at most 1400000. Overrides the network's compute_unit_limit in Captain.toml."
+        )]
+        compute_unit_limit: Option<u32>,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt for mainnet.")]
+        yes: bool,
+        #[clap(long)]
+        #[clap(about = "Skip checking that the deployer can pay for the deployment.")]
+        skip_balance_check: bool,
+        #[clap(long, default_value = "1")]
+        #[clap(
+            about = "Times to retry transactions that fail with an expired blockhash or a timeout."
+        )]
+        max_retries: u32,
+        #[clap(long)]
+        #[clap(
+            about = "Keypair paying for the deployment instead of the network's deployer. Accepts a file path or any Solana keypair URL."
+        )]
+        deployer: Option<String>,
+        #[clap(long, conflicts_with = "deployer")]
+        #[clap(
+            about = "Environment variable holding the keypair JSON of the deployer, e.g. a CI secret. It is written to a temporary file for the Solana CLI."
+        )]
+        keypair_env: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Fail instead of warning if the program binary is older than its source.")]
+        strict: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Fail instead of warning if the installed Anchor CLI does not match anchor_version in Captain.toml."
+        )]
+        strict_anchor: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Largest program binary, in bytes, to deploy. Overrides max_program_size in Captain.toml."
+        )]
+        max_program_size: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Keypair which pays for the deploy and buffer transactions instead of the deployer, e.g. a dedicated gas wallet."
+        )]
+        fee_payer: Option<String>,
+        #[clap(long = "signer", parse(try_from_str = keypair::parse_signer))]
+        #[clap(
+            about = "Additional signer of the deploy and buffer transactions: a keypair file, a usb:// or prompt:// URL, or PUBKEY=SIGNATURE. May be given multiple times."
+        )]
+        signers: Vec<String>,
+        #[clap(long, conflicts_with = "network")]
+        #[clap(
+            about = "RPC URL to deploy to instead of the network's. Keypairs are still taken from the default network in Captain.toml."
+        )]
+        network_url: Option<String>,
+        #[clap(long, conflicts_with = "all")]
+        #[clap(
+            about = "Prebuilt program binary to deploy instead of the released artifact. With --program-id, target/deploy is not needed."
+        )]
+        bin: Option<PathBuf>,
+        #[clap(long, conflicts_with = "all")]
+        #[clap(
+            about = "Program id to deploy to instead of the one in program_keypairs. Either a keypair file, or an address whose keypair is in program_keypairs or target/deploy."
+        )]
+        program_id: Option<ProgramIdOverride>,
+        #[clap(long, conflicts_with_all = &["all", "program-id"])]
+        #[clap(
+            about = "Keypair file whose address is deployed to and which signs the initial deploy, e.g. one materialized from a secrets vault."
+        )]
+        program_id_from: Option<PathBuf>,
+        #[clap(long, conflicts_with_all = &["all", "bin", "idl-only"])]
+        #[clap(
+            about = "Deploys from a buffer which was already written, e.g. for a large program, instead of writing one. Its authority must be the deployer."
+        )]
+        from_buffer: Option<Pubkey>,
+        #[clap(long, requires = "from-buffer")]
+        #[clap(
+            about = "Simulates the deploy transaction from --from-buffer over RPC and prints its logs and compute units instead of sending it."
+        )]
+        simulate: bool,
+        #[clap(long = "final", conflicts_with_all = &["simulate", "idl-only"])]
+        #[clap(
+            about = "Deploys the program without an upgrade authority, so it can never be upgraded or closed. Requires --yes."
+        )]
+        is_final: bool,
+        #[clap(long, conflicts_with_all = &["simulate", "idl-only"])]
+        #[clap(
+            about = "Save which steps of the deploy completed to .captain/deploys/, and skip the steps a previous --resumable run completed."
+        )]
+        resumable: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Check that the program account is executable and owned by the upgradeable loader before reporting success."
+        )]
+        verify_deploy: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Wait until the deploy transaction has this many confirmations before reporting success."
+        )]
+        wait_confirmations: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Also writes the JSON result, including the artifact hash and a timestamp, to this file."
+        )]
+        summary_out: Option<PathBuf>,
+    },
+    #[clap(about = "Upgrades a program.")]
+    Upgrade {
+        #[clap(short, long)]
+        #[clap(
+            about = "Version of the program to upgrade to. Defaults to the version in its Cargo.toml."
+        )]
+        version: Option<Version>,
         #[clap(short, long)]
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
         #[clap(short, long)]
-        #[clap(about = "Network to deploy to")]
         #[clap(
-            default_value = Network::Devnet.into(),
-            possible_values = Network::VARIANTS
+            about = "Network to deploy to. Defaults to default_network in Captain.toml, or devnet."
         )]
-        network: Network,
-        #[clap(short, long)]
-        #[clap(about = "Skip the Anchor IDL upload.")]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(short, long, alias = "skip-idl")]
+        #[clap(about = "Skip all Anchor IDL steps.")]
         skip_anchor_idl: bool,
+        #[clap(long, conflicts_with = "skip-anchor-idl")]
+        #[clap(about = "Only run the Anchor IDL steps, leaving the program as it is.")]
+        idl_only: bool,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit price in micro-lamports. Overrides the network's priority_fee in Captain.toml."
+        )]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit limit of deploy transactions, at most 1400000. Overrides the network's compute_unit_limit in Captain.toml."
+        )]
+        compute_unit_limit: Option<u32>,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt for mainnet.")]
+        yes: bool,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the upgrade authority. Accepts a file path or any Solana keypair URL, e.g. usb://ledger?key=0 or prompt://."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long)]
+        #[clap(
+            about = "Environment variable holding the keypair JSON of the deployer, e.g. a CI secret. It is written to a temporary file for the Solana CLI."
+        )]
+        keypair_env: Option<String>,
+        #[clap(long)]
+        #[clap(
+            about = "Extends the program data account first if the new binary does not fit in it. The upgrade authority pays for it."
+        )]
+        auto_extend: bool,
+        #[clap(long)]
+        #[clap(about = "Skip checking that the deployer can pay for the deployment.")]
+        skip_balance_check: bool,
+        #[clap(long, default_value = "1")]
+        #[clap(
+            about = "Times to retry transactions that fail with an expired blockhash or a timeout."
+        )]
+        max_retries: u32,
+        #[clap(long)]
+        #[clap(about = "Upgrade even if the on-chain program is already identical.")]
+        force: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Stop after handing the buffer to the upgrade authority and print the upgrade instruction to propose to the multisig."
+        )]
+        multisig: bool,
+        #[clap(long)]
+        #[clap(about = "Fail instead of warning if the program binary is older than its source.")]
+        strict: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Fail instead of warning if the installed Anchor CLI does not match anchor_version in Captain.toml."
+        )]
+        strict_anchor: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Largest program binary, in bytes, to deploy. Overrides max_program_size in Captain.toml."
+        )]
+        max_program_size: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Keypair which pays for the deploy and buffer transactions instead of the deployer, e.g. a dedicated gas wallet."
+        )]
+        fee_payer: Option<String>,
+        #[clap(long = "signer", parse(try_from_str = keypair::parse_signer))]
+        #[clap(
+            about = "Additional signer of the deploy and buffer transactions: a keypair file, a usb:// or prompt:// URL, or PUBKEY=SIGNATURE. May be given multiple times."
+        )]
+        signers: Vec<String>,
+        #[clap(long, conflicts_with = "network")]
+        #[clap(
+            about = "RPC URL to deploy to instead of the network's. Keypairs are still taken from the default network in Captain.toml."
+        )]
+        network_url: Option<String>,
+        #[clap(long)]
+        #[clap(
+            about = "Check that the program account is executable and owned by the upgradeable loader before reporting success."
+        )]
+        verify_deploy: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Wait until the deploy transaction has this many confirmations before reporting success."
+        )]
+        wait_confirmations: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Also writes the JSON result, including the artifact hash and a timestamp, to this file."
+        )]
+        summary_out: Option<PathBuf>,
     },
-    #[clap(about = "Upgrades a program.")]
-    Upgrade {
+    #[clap(about = "Upgrades a program back to a previously released version.")]
+    Rollback {
         #[clap(short, long)]
+        #[clap(about = "Version to roll back to. Must be in the artifactory.")]
         version: Version,
         #[clap(short, long)]
         #[clap(about = "Name of the program in target/deploy/<id>.so")]
         program: String,
         #[clap(short, long)]
-        #[clap(about = "Network to deploy to")]
         #[clap(
-            default_value = Network::Devnet.into(),
-            possible_values = Network::VARIANTS
+            about = "Network to deploy to. Defaults to default_network in Captain.toml, or devnet."
         )]
-        network: Network,
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit price in micro-lamports. Overrides the network's priority_fee in Captain.toml."
+        )]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit limit of deploy transactions, at most 1400000. Overrides the network's compute_unit_limit in Captain.toml."
+        )]
+        compute_unit_limit: Option<u32>,
         #[clap(short, long)]
-        #[clap(about = "Skip the Anchor IDL upload.")]
-        skip_anchor_idl: bool,
+        #[clap(about = "Skip the confirmation prompt for mainnet.")]
+        yes: bool,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the upgrade authority. Accepts a file path or any Solana keypair URL, e.g. usb://ledger?key=0 or prompt://."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Skip checking that the deployer can pay for the deployment.")]
+        skip_balance_check: bool,
+        #[clap(long, default_value = "1")]
+        #[clap(
+            about = "Times to retry transactions that fail with an expired blockhash or a timeout."
+        )]
+        max_retries: u32,
+        #[clap(long)]
+        #[clap(about = "Upgrade even if the on-chain program is already identical.")]
+        force: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Also writes the JSON result, including the artifact hash and a timestamp, to this file."
+        )]
+        summary_out: Option<PathBuf>,
+    },
+    #[clap(about = "Finishes an interrupted upgrade from a buffer that was already written.")]
+    ResumeUpgrade {
+        #[clap(short, long)]
+        #[clap(about = "Version the buffer holds. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(about = "Buffer holding the new program, owned by the upgrade authority.")]
+        buffer: Pubkey,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to deploy to. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit price in micro-lamports. Overrides the network's priority_fee in Captain.toml."
+        )]
+        priority_fee: Option<u64>,
+        #[clap(long)]
+        #[clap(
+            about = "Compute unit limit of deploy transactions, at most 1400000. Overrides the network's compute_unit_limit in Captain.toml."
+        )]
+        compute_unit_limit: Option<u32>,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt for mainnet.")]
+        yes: bool,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the upgrade authority. Accepts a file path or any Solana keypair URL, e.g. usb://ledger?key=0 or prompt://."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long, default_value = "1")]
+        #[clap(
+            about = "Times to retry transactions that fail with an expired blockhash or a timeout."
+        )]
+        max_retries: u32,
+        #[clap(long)]
+        #[clap(
+            about = "Also writes the JSON result, including the artifact hash and a timestamp, to this file."
+        )]
+        summary_out: Option<PathBuf>,
+    },
+    #[clap(about = "Extends the program data account so that a larger binary fits.")]
+    Extend {
+        #[clap(short, long)]
+        #[clap(
+            about = "Version of the binary to make room for. Defaults to the version in its Cargo.toml."
+        )]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network of the program. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(
+            about = "Bytes to add. Defaults to the size of the released binary minus the current allocation."
+        )]
+        bytes: Option<usize>,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt for mainnet.")]
+        yes: bool,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the upgrade authority, which pays for the extension. Accepts a file path or any Solana keypair URL."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long, default_value = "1")]
+        #[clap(
+            about = "Times to retry transactions that fail with an expired blockhash or a timeout."
+        )]
+        max_retries: u32,
+    },
+    #[clap(about = "Transfers the upgrade authority of a deployed program.")]
+    SetAuthority {
+        #[clap(short, long)]
+        #[clap(
+            about = "Version whose program keypair holds the address. Defaults to the current version."
+        )]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program.")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network of the program. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long = "new")]
+        #[clap(about = "Address of the new upgrade authority.")]
+        new_authority: Pubkey,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the current upgrade authority. Accepts a file path or any Solana keypair URL."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+        #[clap(short, long)]
+        #[clap(about = "Skip the confirmation prompt.")]
+        yes: bool,
+    },
+    #[clap(about = "Verifies that the deployed program matches the local binary.")]
+    Verify {
+        #[clap(short, long)]
+        #[clap(about = "Version of the program. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to verify against. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(
+            about = "Keeps the dumped on-chain program at this path instead of in a temporary file."
+        )]
+        dump_out: Option<PathBuf>,
+    },
+    #[clap(about = "Writes the on-chain bytes of a program to a file.")]
+    Dump {
+        #[clap(short, long)]
+        #[clap(
+            about = "Version whose program keypair holds the address. Defaults to the current version."
+        )]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program.")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to dump from. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(short, long)]
+        #[clap(about = "File to write the program to.")]
+        out: PathBuf,
+    },
+    #[clap(about = "Compares the on-chain IDL of a program with the local one.")]
+    Diff {
+        #[clap(short, long)]
+        #[clap(about = "Version of the program. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to compare against. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(about = "Exit with a nonzero status if the IDLs differ.")]
+        strict: bool,
+    },
+    #[clap(about = "Lists buffer accounts left behind by failed upgrades.")]
+    ShowBuffers {
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to query. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+        #[clap(long)]
+        #[clap(about = "Close every buffer listed, returning its lamports to the deployer.")]
+        close_all: bool,
+        #[clap(long, env = "UPGRADE_AUTHORITY_KEYPAIR")]
+        #[clap(
+            about = "Keypair of the upgrade authority, needed to close buffers it owns. Accepts a file path or any Solana keypair URL."
+        )]
+        upgrade_authority: Option<String>,
+        #[clap(long)]
+        #[clap(about = "Print the commands that would be run without executing them.")]
+        dry_run: bool,
+    },
+    #[clap(about = "Shows the on-chain state of a program.")]
+    Status {
+        #[clap(short, long)]
+        #[clap(about = "Version of the program. Defaults to the version in its Cargo.toml.")]
+        version: Option<Version>,
+        #[clap(short, long)]
+        #[clap(about = "Name of the program in target/deploy/<id>.so")]
+        program: String,
+        #[clap(short, long)]
+        #[clap(
+            about = "Network to query. Defaults to default_network in Captain.toml, or devnet."
+        )]
+        #[clap(possible_values = Network::VARIANTS)]
+        network: Option<Network>,
+    },
+}
+
+#[derive(Debug, Clap)]
+pub enum ConfigCommand {
+    #[clap(about = "Prints a value, e.g. `captain config get networks.devnet.url`.")]
+    Get {
+        #[clap(about = "Dotted path of the value, e.g. networks.devnet.upgrade_authority.")]
+        key: String,
+    },
+    #[clap(about = "Sets a value, keeping the type of the value it replaces.")]
+    Set {
+        #[clap(about = "Dotted path of the value, e.g. networks.devnet.upgrade_authority.")]
+        key: String,
+        #[clap(
+            about = "New value. Arrays and numbers are written as TOML, e.g. '[\"a\", \"b\"]'."
+        )]
+        value: String,
     },
 }
 
@@ -84,19 +740,84 @@ pub enum SubCommand {
 #[clap(author = crate_authors!())]
 #[clap(setting = AppSettings::ColoredHelp)]
 pub struct Opts {
+    #[clap(long, global = true)]
+    #[clap(
+        about = "Output format. `json` prints a single result object for deploys and upgrades."
+    )]
+    #[clap(
+        default_value = OutputFormat::Human.into(),
+        possible_values = OutputFormat::VARIANTS
+    )]
+    output: OutputFormat,
+    #[clap(short, long, global = true)]
+    #[clap(about = "Only prints essential lines, without the decorative headers.")]
+    quiet: bool,
+    #[clap(short, long, parse(from_occurrences))]
+    #[clap(
+        about = "Logs more details: -v the resolved paths, -vv every command run, -vvv everything. Goes before the subcommand. RUST_LOG overrides it."
+    )]
+    verbose: u64,
+    #[clap(long, global = true)]
+    #[clap(about = "Disables the spinner shown while uploading programs.")]
+    no_progress: bool,
+    #[clap(
+        long,
+        global = true,
+        env = "CAPTAIN_COMMAND_TIMEOUT",
+        default_value = "600"
+    )]
+    #[clap(
+        about = "Seconds after which a hung solana or anchor command is killed. 0 waits forever."
+    )]
+    command_timeout: u64,
+    #[clap(long, global = true)]
+    #[clap(about = "Commitment of the solana commands and of waiting for confirmations.")]
+    #[clap(
+        default_value = Commitment::Confirmed.into(),
+        possible_values = Commitment::VARIANTS
+    )]
+    commitment: Commitment,
+    #[clap(long, global = true, default_value = "3")]
+    #[clap(
+        about = "Times to re-run a deploy or buffer write which failed with an expired blockhash, on top of --max-retries."
+    )]
+    blockhash_retries: u32,
+    #[clap(long, global = true)]
+    #[clap(
+        about = "Skip checking the installed Solana CLI against solana_version in Captain.toml."
+    )]
+    skip_version_check: bool,
+    #[clap(long, global = true)]
+    #[clap(about = "Path to the Captain.toml to use instead of searching parent directories.")]
+    config: Option<PathBuf>,
     #[clap(subcommand)]
     command: SubCommand,
 }
 
-fn main_with_result() -> Result<()> {
-    let opts: Opts = Opts::parse();
+fn main_with_result(opts: Opts) -> Result<()> {
+    let output = Output {
+        format: opts.output,
+        quiet: opts.quiet,
+    };
+    let config_path = opts.config.as_deref();
+    command::set_stdout_to_stderr(output.is_json());
+    command::set_progress(!opts.no_progress);
+    command::set_timeout_secs(opts.command_timeout);
+    command::set_commitment(opts.commitment);
+    command::set_blockhash_retries(opts.blockhash_retries);
+    command::set_skip_version_check(opts.skip_version_check);
 
     match opts.command {
-        SubCommand::Init => {
-            if std::env::current_dir()?.join("Captain.toml").exists() {
+        SubCommand::Init {
+            anchor,
+            keys,
+            force,
+        } => {
+            if std::env::current_dir()?.join("Captain.toml").exists() && !force {
                 println!(
                     "{}",
-                    "Captain.toml has already been initialized in this directory.".red()
+                    "Captain.toml has already been initialized in this directory. Pass --force to replace it."
+                        .red()
                 );
                 std::process::exit(1);
             }
@@ -107,6 +828,18 @@ fn main_with_result() -> Result<()> {
                 );
                 std::process::exit(1);
             }
+            let anchor_config = if anchor {
+                let anchor_path = std::env::current_dir()?.join("Anchor.toml");
+                if !anchor_path.exists() {
+                    return Err(anyhow!(
+                        "--anchor was passed, but Anchor.toml does not exist in the current working directory."
+                    ));
+                }
+                Some(anchor::AnchorConfig::read(&anchor_path)?)
+            } else {
+                None
+            };
+
             let mut cfg = Config::default();
 
             let deployers_root = PathBuf::from("./.captain/deployers/");
@@ -118,11 +851,13 @@ fn main_with_result() -> Result<()> {
                 Network::Testnet,
                 Network::Localnet,
             ] {
-                let deployer_kp = solana_sdk::signer::keypair::Keypair::new();
-                let deployer_path =
-                    deployers_root.join(format!("{}/deployer.json", network.to_string()));
-                solana_sdk::signer::keypair::write_keypair_file(&deployer_kp, &deployer_path)
-                    .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
+                let deployer_path = deployers_root.join(format!("{}/deployer.json", network));
+                // Re-initializing must not lose a deployer which may hold funds.
+                if !deployer_path.exists() {
+                    let deployer_kp = solana_sdk::signer::keypair::Keypair::new();
+                    solana_sdk::signer::keypair::write_keypair_file(&deployer_kp, &deployer_path)
+                        .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
+                }
 
                 let networks = &mut cfg.networks;
                 networks.insert(
@@ -131,55 +866,444 @@ fn main_with_result() -> Result<()> {
                         deployer: CaptainPath(deployer_path),
                         url: network.url().to_string().into(),
                         ws_url: network.ws_url().to_string().into(),
-                        upgrade_authority: "~/.config/solana/id.json".to_string(),
+                        upgrade_authorities: vec![anchor_config
+                            .as_ref()
+                            .and_then(|a| a.provider.as_ref())
+                            .map(|p| p.wallet.clone())
+                            .unwrap_or_else(|| "~/.config/solana/id.json".to_string())],
+                        priority_fee: None,
+                        compute_unit_limit: None,
+                        pre_deploy: None,
+                        post_deploy: None,
                     },
                 );
             }
 
+            if let Some(anchor_config) = &anchor_config {
+                // Anchor declares addresses per cluster, but Captain uses one address per
+                // program, so only fill it in if all clusters agree.
+                let mut addresses: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+                for programs in anchor_config.programs.values() {
+                    for (name, address) in programs {
+                        addresses.entry(name).or_default().push(address);
+                    }
+                }
+                for (name, addresses) in addresses {
+                    let address = if addresses.iter().all(|a| *a == addresses[0]) {
+                        Some(addresses[0].parse().map_err(|_| {
+                            format_err!("invalid address {} for program {}", addresses[0], name)
+                        })?)
+                    } else {
+                        println!(
+                            "{}",
+                            format!(
+                                "Program {} has different addresses per cluster in Anchor.toml; leaving its address unset.",
+                                name
+                            )
+                            .yellow()
+                        );
+                        None
+                    };
+                    cfg.programs.insert(
+                        name.to_string(),
+                        ProgramConfig {
+                            address,
+                            ..ProgramConfig::default()
+                        },
+                    );
+                }
+            }
+
+            if keys {
+                let root = std::env::current_dir()?;
+                std::fs::create_dir_all(cfg.paths.program_keypairs.as_path_buf())?;
+                for program in workspace::list_source_programs(&root)? {
+                    if let Some(address) = cfg.programs.get(&program).and_then(|p| p.address) {
+                        println!(
+                            "Program {} is already declared at {}; not generating a keypair.",
+                            program, address
+                        );
+                        continue;
+                    }
+                    let version = workspace::get_program_version(&program, &root)?;
+                    let kp_path = cfg.program_kp_path(&version, &program);
+                    let program_key = if kp_path.exists() {
+                        solana_sdk::signer::keypair::read_keypair_file(&kp_path)
+                            .map_err(|_| {
+                                format_err!("could not read kp file {}", kp_path.display())
+                            })?
+                            .pubkey()
+                    } else {
+                        let kp = solana_sdk::signer::keypair::Keypair::new();
+                        solana_sdk::signer::keypair::write_keypair_file(&kp, &kp_path).map_err(
+                            |_| {
+                                format_err!("could not write program keypair {}", kp_path.display())
+                            },
+                        )?;
+                        kp.pubkey()
+                    };
+                    cfg.programs.entry(program.clone()).or_default().address = Some(program_key);
+                    println!(
+                        "Program {}: {} ({})",
+                        program,
+                        program_key,
+                        kp_path.display()
+                    );
+                }
+            }
+
             let toml = toml::to_string(&cfg)?;
+            if Path::new("Captain.toml").exists() {
+                std::fs::rename("Captain.toml", "Captain.toml.bak")?;
+                println!("Backed up the existing Captain.toml to Captain.toml.bak");
+            }
             let mut file = File::create("Captain.toml")?;
             file.write_all(toml.as_bytes())?;
+
+            if keys {
+                println!();
+                println!("Fund the deployers before deploying:");
+                for (network, network_config) in &cfg.networks {
+                    let deployer =
+                        solana_sdk::signer::keypair::read_keypair_file(&network_config.deployer.0)
+                            .map_err(|_| {
+                                format_err!(
+                                    "could not read deployer keypair {}",
+                                    network_config.deployer.0.display()
+                                )
+                            })?;
+                    println!("  {:<14} {}", network.to_string(), deployer.pubkey());
+                }
+            }
         }
-        SubCommand::Build => {
-            let (_, _, root) = Config::discover()?;
-            if root.join("Anchor.toml").exists() {
-                println!("{}", "Anchor found! Running `anchor build -v`.".green());
-                command::exec(Command::new("anchor").arg("build").arg("-v"))?;
-            } else {
+        SubCommand::Completions { shell } => {
+            let mut app = Opts::into_app();
+            let stdout = &mut std::io::stdout();
+            match shell {
+                Shell::Bash => generate::<Bash, _>(&mut app, "captain", stdout),
+                Shell::Zsh => generate::<Zsh, _>(&mut app, "captain", stdout),
+                Shell::Fish => generate::<Fish, _>(&mut app, "captain", stdout),
+                Shell::PowerShell => generate::<PowerShell, _>(&mut app, "captain", stdout),
+                Shell::Elvish => generate::<Elvish, _>(&mut app, "captain", stdout),
+            }
+        }
+        SubCommand::Check => {
+            // Discovery validates the config.
+            let (_, _, root) = Config::discover(config_path)?;
+            let path = config_path
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| root.join("Captain.toml"));
+            println!("{}", format!("{} is valid.", path.display()).green());
+        }
+        SubCommand::Doctor { network } => doctor(config_path, network)?,
+        SubCommand::Whoami { network } => {
+            let (config, _, root) = Config::discover(config_path)?;
+            let networks: Vec<&Network> = match &network {
+                Some(network) => {
+                    config.network_config(network)?;
+                    vec![network]
+                }
+                None => config.networks.keys().collect(),
+            };
+            for network in networks {
+                let network_config = config.network_config(network)?;
+                let deployer_path = network_config.deployer.as_path_buf();
+                let deployer = solana_sdk::signer::keypair::read_keypair_file(&deployer_path)
+                    .map(|kp| kp.pubkey().to_string())
+                    .unwrap_or_else(|_| "could not read keypair".red().to_string());
+                let upgrade_authority = network_config
+                    .upgrade_authority_pubkey()
+                    .map(|pubkey| pubkey.to_string())
+                    .unwrap_or_else(|e| e.to_string().red().to_string());
+                println!("{}", network.to_string().bold());
+                println!(
+                    "    RPC URL:           {}",
+                    network_config
+                        .url
+                        .clone()
+                        .unwrap_or_else(|| network.url().to_string())
+                );
+                println!(
+                    "    Deployer:          {} ({})",
+                    deployer,
+                    deployer_path.display()
+                );
+                println!("    Upgrade authority: {}", upgrade_authority);
+            }
+
+            let mut programs = workspace::list_source_programs(&root)?;
+            programs.extend(config.programs.keys().cloned());
+            programs.sort();
+            programs.dedup();
+            if !programs.is_empty() {
+                println!("{}", "programs".bold());
+            }
+            for program in programs {
+                let declared = config.programs.get(&program).and_then(|p| p.address);
+                let version = workspace::current_version(&config, &program, &root)
+                    .map(|(version, _)| version);
+                let address = match (declared, version) {
+                    (Some(address), _) => format!("{} (Captain.toml)", address),
+                    (None, Ok(version)) => {
+                        let kp_path = config.program_kp_path(&version, &program);
+                        match solana_sdk::signer::keypair::read_keypair_file(&kp_path) {
+                            Ok(kp) => format!("{} ({})", kp.pubkey(), kp_path.display()),
+                            Err(_) => format!("no keypair at {}", kp_path.display())
+                                .yellow()
+                                .to_string(),
+                        }
+                    }
+                    (None, Err(e)) => e.to_string().red().to_string(),
+                };
+                println!("    {}: {}", program, address);
+            }
+        }
+        SubCommand::Build {
+            program,
+            verifiable,
+            network,
+            jobs,
+        } => {
+            let (config, _, root) = Config::discover(config_path)?;
+            let build_command = config.build_command;
+            let parallel = jobs > 1 && program.is_none();
+            if parallel && (verifiable || root.join("Anchor.toml").exists()) {
                 println!(
                     "{}",
-                    "Anchor.toml not found in workspace root. Running `cargo build-bpf`.".yellow()
+                    "--jobs only applies to cargo builds outside of Anchor without --verifiable. Building serially."
+                        .yellow()
                 );
-                command::exec(Command::new("cargo").arg("build-bpf"))?;
+            }
+            match &program {
+                Some(program) => build(
+                    &root,
+                    Some(program),
+                    verifiable,
+                    &config.build_features(program, network.as_ref()),
+                    build_command,
+                )?,
+                None if parallel && !verifiable && !root.join("Anchor.toml").exists() => {
+                    let mut programs = vec![];
+                    for program in workspace::list_source_programs(&root)? {
+                        if config.is_excluded(&program) {
+                            println!("Skipping excluded program {}", program);
+                            continue;
+                        }
+                        let features = config.build_features(&program, network.as_ref());
+                        programs.push((program, features));
+                    }
+                    build_parallel(&root, &programs, build_command, jobs)?;
+                }
+                // The whole workspace is built at once, so excluded programs have
+                // to be left out by building the others on their own.
+                None if !config.exclude.is_empty() => {
+                    for program in workspace::list_source_programs(&root)? {
+                        if config.is_excluded(&program) {
+                            println!("Skipping excluded program {}", program);
+                            continue;
+                        }
+                        let features = config.build_features(&program, network.as_ref());
+                        build(&root, Some(&program), verifiable, &features, build_command)?;
+                    }
+                }
+                None => {
+                    build(&root, None, verifiable, &[], build_command)?;
+                    // Features apply to a single package, so programs with features are
+                    // rebuilt on their own.
+                    for program in config.programs.keys() {
+                        let features = config.build_features(program, network.as_ref());
+                        if !features.is_empty() {
+                            build(&root, Some(program), verifiable, &features, build_command)?;
+                        }
+                    }
+                }
             }
         }
-        SubCommand::Airdrop { amount: _amount } => {
-            // let workspace = &workspace::load(program.as_str(), version, network.clone())?;
-            // command::exec(solana_cmd!(workspace).arg("airdrop").arg(amount))?;
-            println!("Unimplemented")
+        SubCommand::Fund {
+            ref network,
+            amount,
+            max_retries,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            if *network == Network::Mainnet {
+                return Err(anyhow!("Airdrops are not available on {}.", network));
+            }
+            let (config, _, _) = Config::discover(config_path)?;
+            let network_config = config.network_config(network)?;
+            let url = network_config
+                .url
+                .clone()
+                .unwrap_or_else(|| network.url().to_string());
+            let deployer_path = network_config.deployer.as_path_buf();
+            let deployer = solana_sdk::signer::keypair::read_keypair_file(&deployer_path)
+                .map_err(|_| {
+                    format_err!(
+                        "could not read deployer keypair {}",
+                        deployer_path.display()
+                    )
+                })?
+                .pubkey();
+
+            println!("Airdropping {} SOL to {} on {}", amount, deployer, network);
+            airdrop(&url, &deployer, amount, max_retries)?;
+            println!("{}", "Fund success!".green());
         }
-        SubCommand::Programs => {
-            let (config, _, root) = Config::discover()?;
-            let paths = std::fs::read_dir(root.join("./target/deploy/")).unwrap();
-            for path in paths {
-                let the_path = path?.path();
-                if the_path.extension().and_then(|ex| ex.to_str()) != Some("so") {
-                    continue;
+        SubCommand::Localnet {
+            program,
+            all,
+            teardown,
+            reset,
+            skip_anchor_idl,
+            startup_timeout,
+        } => {
+            let (config, _, root) = Config::discover(config_path)?;
+            let network = Network::Localnet;
+            let network_config = config.network_config(&network)?;
+            let url = network_config
+                .url
+                .clone()
+                .unwrap_or_else(|| network.url().to_string());
+            let deployer_path = network_config.deployer.as_path_buf();
+            let deployer = solana_sdk::signer::keypair::read_keypair_file(&deployer_path)
+                .map_err(|_| {
+                    format_err!(
+                        "could not read deployer keypair {}",
+                        deployer_path.display()
+                    )
+                })?
+                .pubkey();
+
+            let validator = start_test_validator(&root, &url, reset, startup_timeout)?;
+            let result = (|| -> Result<()> {
+                println!("Airdropping {} SOL to {} on {}", 100, deployer, network);
+                airdrop(&url, &deployer, 100.0, 3)?;
+                let programs = if all {
+                    workspace::list_programs(&root)?
+                        .into_iter()
+                        .filter(|program| !config.is_excluded(program))
+                        .collect()
+                } else {
+                    program
+                };
+                let opts = DeployOptions {
+                    version: None,
+                    network: network.clone(),
+                    output,
+                    skip_anchor_idl,
+                    idl_only: false,
+                    priority_fee: None,
+                    compute_unit_limit: None,
+                    yes: true,
+                    skip_balance_check: false,
+                    max_retries: 3,
+                    config_path: config_path.map(Path::to_path_buf),
+                    deployer: None,
+                    strict: false,
+                    strict_anchor: false,
+                    max_program_size: None,
+                    fee_payer: None,
+                    signers: vec![],
+                    network_url: Some(url.clone()),
+                    overrides: ProgramOverrides::default(),
+                    from_buffer: None,
+                    simulate: false,
+                    verify_deploy: false,
+                    wait_confirmations: None,
+                    label: None,
+                    is_final: false,
+                    resumable: false,
+                };
+                for program in &programs {
+                    deploy_program(program, &opts)?;
                 }
+                Ok(())
+            })();
 
-                let program = the_path
-                    .file_stem()
-                    .ok_or_else(|| format_err!("no file stem"))?
-                    .to_str()
-                    .ok_or_else(|| format_err!("no str"))?;
+            if let Some(mut validator) = validator {
+                if teardown {
+                    println!("Stopping solana-test-validator");
+                    validator.kill()?;
+                    validator.wait()?;
+                } else {
+                    println!(
+                        "solana-test-validator is still running with pid {}. Stop it with `kill {}`.",
+                        validator.id(),
+                        validator.id()
+                    );
+                }
+            }
+            result?;
+            println!("{}", "Localnet deploy success!".green());
+        }
+        SubCommand::Config { command } => {
+            let path = Config::find(config_path)?;
+            match command {
+                ConfigCommand::Get { key } => match config::get_value(&path, &key)? {
+                    Some(toml::Value::String(value)) => println!("{}", value),
+                    Some(toml::Value::Table(table)) => print!("{}", toml::to_string(&table)?),
+                    Some(value) => println!("{}", value),
+                    None => return Err(anyhow!("{} is not set in {}", key, path.display())),
+                },
+                ConfigCommand::Set { key, value } => {
+                    config::set_value(&path, &key, &value)?;
+                    println!("Set {} in {}", key, path.display());
+                }
+            }
+        }
+        SubCommand::Clean { artifacts, yes } => {
+            let (config, _, root) = Config::discover(config_path)?;
+            // Only the binaries: target/deploy also holds the program keypairs which
+            // `anchor build` and `anchor keys` use as the program IDs.
+            let deploy_dir = root.join("target").join("deploy");
+            let mut files = vec![];
+            if deploy_dir.exists() {
+                for entry in std::fs::read_dir(&deploy_dir)? {
+                    let path = entry?.path();
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    if name.ends_with(".so") || name.ends_with(".so.sha256") {
+                        files.push(path);
+                    }
+                }
+                files.sort();
+            }
+            let mut dirs = vec![root.join(".captain").join("buffers")];
+            if artifacts {
+                dirs.push(config.paths.artifacts.as_path_buf());
+            }
+            let dirs: Vec<_> = dirs.into_iter().filter(|dir| dir.exists()).collect();
+            if files.is_empty() && dirs.is_empty() {
+                println!("Nothing to clean.");
+                return Ok(());
+            }
 
-                let program_version = workspace::get_program_version(program, &root).ok();
+            println!("The following will be deleted:");
+            for path in files.iter().chain(&dirs) {
+                println!("  {}", path.display());
+            }
+            if !yes && !confirm("Continue? [y/N] ")? {
+                return Err(anyhow!("Aborted."));
+            }
+            for file in &files {
+                std::fs::remove_file(file)?;
+            }
+            for dir in &dirs {
+                std::fs::remove_dir_all(dir)?;
+            }
+            println!("{}", "Clean success!".green());
+        }
+        SubCommand::Programs => {
+            let (config, _, root) = Config::discover(config_path)?;
+            for program in workspace::list_programs(&root)? {
+                let program = program.as_str();
+                let program_version = workspace::current_version(&config, program, &root)
+                    .ok()
+                    .map(|(version, _)| version);
 
                 let program_key = program_version
                     .clone()
                     .and_then(|version| {
                         solana_sdk::signer::keypair::read_keypair_file(
-                            &config.program_kp_path(&version, program),
+                            config.program_kp_path(&version, program),
                         )
                         .ok()
                     })
@@ -201,8 +1325,44 @@ fn main_with_result() -> Result<()> {
                 println!();
             }
         }
-        SubCommand::Release { program } => {
-            let workspace = &workspace::load(program.as_str(), None, Network::Localnet)?;
+        SubCommand::ListVersions { program } => {
+            let (config, _, root) = Config::discover(config_path)?;
+            let versions = config.artifact_versions(&program)?;
+            if versions.is_empty() {
+                println!("No versions of {} have been released.", program);
+                return Ok(());
+            }
+
+            println!("{:<16} {:>12}  SHA-256", "VERSION", "SIZE");
+            for version in versions {
+                let artifact_paths = config.artifact_paths(&version, &program);
+                let bin = std::fs::read(&artifact_paths.bin)?;
+                let sha256 = workspace::sha256_hex(&bin);
+                let note = match workspace::read_sha256(&artifact_paths.sha256)? {
+                    Some(recorded) if recorded != sha256 => {
+                        format!(" {}", "(does not match recorded SHA-256)".red())
+                    }
+                    _ => String::new(),
+                };
+                let labels = deployments::labels(&root, &program, &version.to_string())?;
+                let labels = if labels.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", labels.join(", "))
+                };
+                println!(
+                    "{:<16} {:>12}  {}{}{}",
+                    version.to_string(),
+                    bin.len(),
+                    sha256,
+                    labels,
+                    note
+                );
+            }
+        }
+        SubCommand::Release { program, strict } => {
+            let workspace =
+                &workspace::load(program.as_str(), None, Network::Localnet, config_path)?;
             if workspace.artifact_paths.exist() {
                 return Err(anyhow!("Program artifacts already exist for this version. Make sure to bump your Cargo.toml."));
             }
@@ -211,195 +1371,1117 @@ fn main_with_result() -> Result<()> {
                 "Releasing program {} with version {}",
                 program, workspace.deploy_version
             );
+            workspace.check_stale(&program, strict)?;
 
-            output_header("Copying artifacts");
+            output_header(output, "Copying artifacts");
             workspace.copy_artifacts()?;
+            for path in &[
+                &workspace.artifact_paths.bin,
+                &workspace.artifact_paths.idl,
+                &workspace.artifact_paths.sha256,
+            ] {
+                println!("Saved {}", path.display());
+            }
 
             println!("Release success!");
         }
         SubCommand::Deploy {
             version,
             program,
-            ref network,
+            all,
+            since,
+            fail_fast,
+            label,
+            network,
             skip_anchor_idl,
+            idl_only,
+            dry_run,
+            priority_fee,
+            compute_unit_limit,
+            yes,
+            skip_balance_check,
+            max_retries,
+            deployer,
+            keypair_env,
+            strict,
+            strict_anchor,
+            max_program_size,
+            fee_payer,
+            signers,
+            network_url,
+            bin,
+            program_id,
+            program_id_from,
+            from_buffer,
+            simulate,
+            is_final,
+            resumable,
+            verify_deploy,
+            wait_confirmations,
+            summary_out,
         } => {
-            let workspace = &workspace::load(program.as_str(), version.into(), network.clone())?;
-            println!(
-                "Deploying program {} with version {}",
-                program, workspace.deploy_version
+            let network = resolve_network(network, config_path, output)?;
+            if is_final && !yes {
+                return Err(anyhow!(
+                    "--final deploys programs which can never be upgraded or closed. Pass --yes to confirm."
+                ));
+            }
+            command::set_dry_run(dry_run);
+            // Kept until the end of the deploy, when the file is deleted.
+            let deployer_file = keypair_env.as_deref().map(keypair::from_env).transpose()?;
+            let deployer = match &deployer_file {
+                Some(file) => Some(file.path().to_string_lossy().into_owned()),
+                None => deployer,
+            };
+            if let Some(deployer) = &deployer {
+                keypair::validate(deployer)?;
+            }
+            let mut programs = if all || program.is_empty() {
+                let (config, _, root) = Config::discover(config_path)?;
+                workspace::list_programs(&root)?
+                    .into_iter()
+                    .filter(|program| !config.is_excluded(program))
+                    .collect()
+            } else {
+                program
+            };
+            let mut unchanged = vec![];
+            if let Some(since) = &since {
+                let (_, _, root) = Config::discover(config_path)?;
+                let changed = workspace::changed_since(&root, since, &programs)?;
+                let (changed_programs, unchanged_programs): (Vec<_>, Vec<_>) = programs
+                    .into_iter()
+                    .zip(changed)
+                    .partition(|(_, changed)| *changed);
+                programs = changed_programs.into_iter().map(|(p, _)| p).collect();
+                unchanged = unchanged_programs.into_iter().map(|(p, _)| p).collect();
+                for program in &unchanged {
+                    status!(output, "Skipping {}: unchanged since {}", program, since);
+                }
+            }
+            let program_id = match program_id_from {
+                Some(path) => {
+                    let keypair = solana_sdk::signer::keypair::read_keypair_file(&path)
+                        .map_err(|_| format_err!("could not read keypair {}", path.display()))?;
+                    status!(
+                        output,
+                        "Using program id {} from {}",
+                        keypair.pubkey(),
+                        path.display()
+                    );
+                    Some(ProgramIdOverride::Keypair(path))
+                }
+                None => program_id,
+            };
+            if (bin.is_some() || program_id.is_some()) && programs.len() != 1 {
+                return Err(anyhow!(
+                    "--bin and --program-id can only be used when deploying a single program."
+                ));
+            }
+            let opts = DeployOptions {
+                version,
+                network,
+                output,
+                skip_anchor_idl,
+                idl_only,
+                priority_fee,
+                compute_unit_limit,
+                yes,
+                skip_balance_check,
+                max_retries,
+                config_path: config_path.map(Path::to_path_buf),
+                deployer,
+                strict,
+                strict_anchor,
+                max_program_size,
+                fee_payer,
+                signers,
+                network_url,
+                overrides: ProgramOverrides {
+                    bin,
+                    id: program_id,
+                },
+                from_buffer,
+                simulate,
+                verify_deploy,
+                wait_confirmations,
+                label,
+                is_final,
+                resumable,
+            };
+
+            let mut results = vec![];
+            let mut last_err = None;
+            for program in &programs {
+                match deploy_program(program, &opts) {
+                    Ok(result) => results.push(result),
+                    Err(err) if fail_fast || programs.len() == 1 => return Err(err),
+                    Err(err) => {
+                        status!(
+                            output,
+                            "{}",
+                            format!("Failed to deploy {}: {}", program, err).red()
+                        );
+                        results.push(DeployOutput::failure(
+                            program,
+                            opts.network_url
+                                .clone()
+                                .unwrap_or_else(|| opts.network.to_string()),
+                            &err,
+                        ));
+                        last_err = Some(err);
+                    }
+                }
+            }
+
+            let network_label = opts
+                .network_url
+                .clone()
+                .unwrap_or_else(|| opts.network.to_string());
+            results.extend(
+                unchanged
+                    .iter()
+                    .map(|program| DeployOutput::unchanged(program, network_label.clone())),
             );
 
-            println!("Address: {}", workspace.program_key);
+            if results.len() > 1 {
+                output_header(output, "Summary");
+                for result in &results {
+                    let outcome = match (&result.error, result.already_deployed) {
+                        _ if result.unchanged => "unchanged, skipped".dimmed().to_string(),
+                        (Some(err), _) => format!("{} {}", "failed:".red(), err),
+                        (None, true) => "already deployed".yellow().to_string(),
+                        (None, false) => "deployed".green().to_string(),
+                    };
+                    status!(
+                        output,
+                        "{} ({}): {}",
+                        result.program,
+                        result.program_key.as_deref().unwrap_or("unknown address"),
+                        outcome
+                    );
+                }
+            }
 
-            if workspace.show_program()? {
-                println!("Program already deployed. Use `captain upgrade` if you want to upgrade the program.");
-                std::process::exit(0);
+            if results.len() == 1 {
+                write_result(output, summary_out.as_deref(), &results[0])?;
+            } else {
+                write_result(output, summary_out.as_deref(), &results)?;
             }
 
-            output_header("Deploying program");
+            if last_err.is_some() {
+                let failed = results.iter().filter(|r| !r.success).count();
+                if output.is_json() {
+                    std::process::exit(1);
+                }
+                return Err(anyhow!(
+                    "{} of {} programs failed to deploy",
+                    failed,
+                    programs.len()
+                ));
+            }
+        }
+        SubCommand::Upgrade {
+            version,
+            program,
+            ref network,
+            skip_anchor_idl,
+            idl_only,
+            dry_run,
+            priority_fee,
+            compute_unit_limit,
+            yes,
+            upgrade_authority,
+            keypair_env,
+            auto_extend,
+            skip_balance_check,
+            max_retries,
+            force,
+            multisig,
+            strict,
+            strict_anchor,
+            max_program_size,
+            fee_payer,
+            signers,
+            network_url,
+            verify_deploy,
+            wait_confirmations,
+            summary_out,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let mut workspace =
+                workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            workspace.network_url_override = network_url;
+            // Kept until the end of the upgrade, when the file is deleted.
+            let deployer_file = keypair_env.as_deref().map(keypair::from_env).transpose()?;
+            if let Some(file) = &deployer_file {
+                workspace.override_deployer(&file.path().to_string_lossy())?;
+            }
+            if let Some(fee_payer) = &fee_payer {
+                workspace.set_fee_payer(fee_payer)?;
+            }
+            workspace.signers = signers;
+            if max_program_size.is_some() {
+                workspace.max_program_size = max_program_size;
+            }
+            let opts = UpgradeOptions {
+                output,
+                upgrade_authority_keypair: upgrade_authority,
+                priority_fee,
+                compute_unit_limit,
+                max_retries,
+                yes,
+                multisig,
+                skip_anchor_idl,
+                idl_only,
+                auto_extend,
+                skip_balance_check,
+                force,
+                strict,
+                strict_anchor,
+                verify_deploy,
+                wait_confirmations,
+            };
+            let result = deploy::upgrade(&workspace, &program, &opts)?;
+            write_result(output, summary_out.as_deref(), &result)?;
+        }
+        SubCommand::Rollback {
+            version,
+            program,
+            ref network,
+            dry_run,
+            priority_fee,
+            compute_unit_limit,
+            yes,
+            upgrade_authority,
+            skip_balance_check,
+            max_retries,
+            force,
+            summary_out,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let (config, _, _) = Config::discover(config_path)?;
+            if !config.artifact_paths(&version, &program).bin.exists() {
+                let versions = config
+                    .artifact_versions(&program)?
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>();
+                return Err(anyhow!(
+                    "No artifact found for {} version {}. Available versions: {}",
+                    program,
+                    version,
+                    if versions.is_empty() {
+                        "none".to_string()
+                    } else {
+                        versions.join(", ")
+                    }
+                ));
+            }
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("deploy")
-                    .arg(&workspace.artifact_paths.bin)
-                    .arg("--program-id")
-                    .arg(&workspace.program_paths.id),
+            let workspace = workspace::load(
+                program.as_str(),
+                version.into(),
+                network.clone(),
+                config_path,
             )?;
+            let opts = UpgradeOptions {
+                output,
+                upgrade_authority_keypair: upgrade_authority,
+                priority_fee,
+                compute_unit_limit,
+                max_retries,
+                yes,
+                multisig: false,
+                skip_anchor_idl: true,
+                idl_only: false,
+                auto_extend: false,
+                skip_balance_check,
+                force,
+                strict: false,
+                strict_anchor: false,
+                verify_deploy: false,
+                wait_confirmations: None,
+            };
+            let result = deploy::rollback(&workspace, &program, &opts)?;
+            write_result(output, summary_out.as_deref(), &result)?;
+        }
+        SubCommand::Extend {
+            version,
+            program,
+            ref network,
+            bytes,
+            dry_run,
+            yes,
+            upgrade_authority,
+            max_retries,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let upgrade_authority_keypair = upgrade_authority.ok_or_else(|| {
+                format_err!(
+                    "Must pass --upgrade-authority or set the UPGRADE_AUTHORITY_KEYPAIR environment variable."
+                )
+            })?;
+            keypair::validate(&upgrade_authority_keypair)?;
 
-            output_header("Setting upgrade authority");
+            let workspace =
+                workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            workspace.require_cli(false)?;
+            if !workspace.show_program()? {
+                return Err(anyhow!(
+                    "Program {} does not exist on {}.",
+                    workspace.program_key,
+                    network
+                ));
+            }
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("set-upgrade-authority")
-                    .arg(&workspace.program_paths.id)
-                    .arg("--new-upgrade-authority")
-                    .arg(&workspace.network_config.upgrade_authority),
-            )?;
+            let onchain_len = workspace.fetch_program()?.data_len;
+            let bytes = match bytes {
+                Some(bytes) => bytes,
+                None => {
+                    let local_len =
+                        std::fs::metadata(&workspace.artifact_paths.bin)?.len() as usize;
+                    status!(
+                        output,
+                        "On-chain size: {} bytes, local size: {} bytes",
+                        onchain_len,
+                        local_len
+                    );
+                    if local_len <= onchain_len {
+                        status!(output, "The program data account is already large enough.");
+                        return Ok(());
+                    }
+                    local_len - onchain_len
+                }
+            };
 
-            workspace.show_program()?;
+            confirm_mainnet(&program, network, yes || dry_run)?;
+            output_header(
+                output,
+                &format!("Extending program data account by {} bytes", bytes),
+            );
+            workspace.extend_program(bytes, Some(&upgrade_authority_keypair), max_retries)?;
+            if dry_run {
+                status!(output, "Dry run complete. No transactions were sent.");
+            } else {
+                status!(output, "Extend success!");
+            }
+        }
+        SubCommand::SetAuthority {
+            version,
+            program,
+            ref network,
+            new_authority,
+            upgrade_authority,
+            dry_run,
+            yes,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let upgrade_authority_keypair = upgrade_authority.ok_or_else(|| {
+                format_err!(
+                    "Must pass --upgrade-authority or set the UPGRADE_AUTHORITY_KEYPAIR environment variable."
+                )
+            })?;
+            keypair::validate(&upgrade_authority_keypair)?;
 
-            if workspace.has_anchor() {
-                if skip_anchor_idl {
-                    output_header("Skipping Anchor IDL upload.");
-                } else {
-                    output_header("Initializing IDL");
-                    command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("init")
-                            .arg(&workspace.program_key.to_string())
-                            .arg("--filepath")
-                            .arg(&workspace.program_paths.idl),
-                    )?;
+            let workspace =
+                workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            workspace.require_cli(false)?;
+            if !workspace.show_program()? {
+                return Err(anyhow!(
+                    "Program {} does not exist on {}.",
+                    workspace.program_key,
+                    network
+                ));
+            }
+            let current_authority =
+                check_upgrade_authority(&workspace, &upgrade_authority_keypair)?;
+            if current_authority == new_authority.to_string() {
+                status!(output, "Upgrade authority is already {}", new_authority);
+                return Ok(());
+            }
 
-                    output_header("Setting IDL authority");
-                    command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("set-authority")
-                            .arg("--program-id")
-                            .arg(workspace.program_key.to_string())
-                            .arg("--new-authority")
-                            .arg(&workspace.network_config.upgrade_authority),
-                    )?;
-                }
+            if new_authority == Pubkey::default() {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "WARNING: {} is the all-ones address, which nobody can sign for. {} will be immutable and can never be upgraded or closed.",
+                        new_authority, program
+                    )
+                    .red()
+                );
+            } else if !workspace
+                .network_config
+                .upgrade_authorities
+                .contains(&new_authority.to_string())
+            {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "WARNING: {} is not an upgrade_authority of {} in Captain.toml. Update it, or later deploys will warn about the mismatch.",
+                        new_authority, network
+                    )
+                    .yellow()
+                );
+            }
+            if !yes
+                && !dry_run
+                && !confirm(&format!(
+                    "Transfer the upgrade authority of {} ({}) on {} from {} to {}? [y/N] ",
+                    program, workspace.program_key, network, current_authority, new_authority
+                ))?
+            {
+                return Err(anyhow!("Aborted."));
             }
 
-            println!("Deployment success!");
+            output_header(output, "Setting upgrade authority");
+            workspace.set_upgrade_authority(
+                Some(&upgrade_authority_keypair),
+                &new_authority.to_string(),
+            )?;
+            if dry_run {
+                status!(output, "Dry run complete. No transactions were sent.");
+            } else {
+                status!(output, "Set authority success!");
+            }
         }
-        SubCommand::Upgrade {
+        SubCommand::ResumeUpgrade {
             version,
             program,
+            buffer,
             ref network,
-            skip_anchor_idl,
+            dry_run,
+            priority_fee,
+            compute_unit_limit,
+            yes,
+            upgrade_authority,
+            max_retries,
+            summary_out,
         } => {
-            let upgrade_authority_keypair =
-                env::var("UPGRADE_AUTHORITY_KEYPAIR").map_err(|_| {
-                    format_err!("Must set UPGRADE_AUTHORITY_KEYPAIR environment variable.")
-                })?;
-
-            let workspace = workspace::load(program.as_str(), version.into(), network.clone())?;
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let workspace =
+                workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            let opts = UpgradeOptions {
+                output,
+                upgrade_authority_keypair: upgrade_authority,
+                priority_fee,
+                compute_unit_limit,
+                max_retries,
+                yes,
+                multisig: false,
+                skip_anchor_idl: true,
+                idl_only: false,
+                auto_extend: false,
+                skip_balance_check: false,
+                force: false,
+                strict: false,
+                strict_anchor: false,
+                verify_deploy: false,
+                wait_confirmations: None,
+            };
+            let result = deploy::resume_upgrade(&workspace, &program, &buffer, &opts)?;
+            write_result(output, summary_out.as_deref(), &result)?;
+        }
+        SubCommand::Verify {
+            version,
+            program,
+            ref network,
+            dump_out,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            let workspace =
+                &workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            workspace.require_cli(false)?;
             println!(
-                "Upgrading program {} with version {}",
+                "Verifying program {} with version {}",
                 program, workspace.deploy_version
             );
+            println!("Address: {}", workspace.program_key);
 
-            if !workspace.show_program()? {
-                println!("Program does not exist. Use `captain deploy` if you want to deploy the program for the first time.");
-                std::process::exit(1);
+            output_header(output, "Dumping on-chain program");
+
+            let dump_file = NamedTempFile::new()?;
+            let dump_path = dump_out.as_deref().unwrap_or_else(|| dump_file.path());
+            workspace.dump_program(dump_path)?;
+            if let Some(dump_out) = &dump_out {
+                println!("Dumped the on-chain program to {}", dump_out.display());
             }
 
-            output_header("Writing buffer");
+            let local = std::fs::read(&workspace.program_paths.bin)?;
+            let onchain = std::fs::read(dump_path)?;
+            let onchain = workspace::trim_program_data(&onchain, &local);
 
-            let buffer_kp = solana_sdk::signer::keypair::Keypair::new();
-            let buffer_key = buffer_kp.pubkey();
-            println!("Buffer Pubkey: {}", buffer_key);
+            println!();
+            println!("Local SHA-256:    {}", workspace::sha256_hex(&local));
+            println!("On-chain SHA-256: {}", workspace::sha256_hex(onchain));
+            if let Some(release) = workspace::read_sha256(&workspace.artifact_paths.sha256)? {
+                println!("Release SHA-256:  {}", release);
+            }
+            println!();
 
-            let mut buffer_file = NamedTempFile::new()?;
-            solana_sdk::signer::keypair::write_keypair(&buffer_kp, &mut buffer_file)
-                .map_err(|_| format_err!("could not generate temp buffer keypair"))?;
+            if local != onchain {
+                println!(
+                    "{}",
+                    format!(
+                        "Verification failed: on-chain program does not match {}",
+                        workspace.program_paths.bin.display()
+                    )
+                    .red()
+                );
+                std::process::exit(1);
+            }
 
-            command::exec(
-                solana_cmd!(workspace)
+            warn_on_authority_mismatch(workspace, &workspace.fetch_program()?.authority);
+            println!("{}", "Verification success!".green());
+        }
+        SubCommand::Dump {
+            version,
+            program,
+            ref network,
+            out,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            let (config, _, root) = Config::discover(config_path)?;
+            let url = config
+                .network_config(network)?
+                .url
+                .clone()
+                .unwrap_or_else(|| network.url().to_string());
+            let address = program_address(&config, &program, &root, version)?;
+            command::require("solana")?;
+            command::exec_output(
+                Command::new("solana")
+                    .arg("--url")
+                    .arg(&url)
+                    .arg("--commitment")
+                    .arg(command::commitment().to_string())
                     .arg("program")
-                    .arg("write-buffer")
-                    .arg(&workspace.artifact_paths.bin)
-                    .arg("--output")
-                    .arg("json")
-                    .arg("--buffer")
-                    .arg(&buffer_file.path()),
+                    .arg("dump")
+                    .arg(address.to_string())
+                    .arg(&out),
             )?;
+            println!(
+                "Dumped {} ({}) on {} to {} ({} bytes)",
+                program,
+                address,
+                network,
+                out.display(),
+                std::fs::metadata(&out)?.len()
+            );
+        }
+        SubCommand::Diff {
+            version,
+            program,
+            ref network,
+            strict,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            let workspace =
+                &workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            workspace.require_cli(true)?;
+            println!(
+                "Comparing IDL of {} at {} with {}",
+                program,
+                workspace.program_key,
+                workspace.program_paths.idl.display()
+            );
 
-            output_header("Setting buffer authority");
+            let onchain = workspace.fetch_idl()?;
+            let local: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&workspace.program_paths.idl)?)
+                    .map_err(|e| {
+                        format_err!(
+                            "could not parse {}: {}",
+                            workspace.program_paths.idl.display(),
+                            e
+                        )
+                    })?;
 
-            command::exec(
-                solana_cmd!(workspace)
-                    .arg("program")
-                    .arg("set-buffer-authority")
-                    .arg(buffer_key.to_string())
-                    .arg("--new-buffer-authority")
-                    .arg(&workspace.network_config.upgrade_authority),
-            )?;
+            let changes = idl::diff(&onchain, &local);
+            if changes.is_empty() {
+                println!("{}", "IDL is up to date.".green());
+                return Ok(());
+            }
+            println!();
+            for change in &changes {
+                println!("{}", change);
+            }
+            println!();
+            println!(
+                "{}",
+                format!("{} change(s) since the on-chain IDL.", changes.len()).yellow()
+            );
+            if strict {
+                std::process::exit(1);
+            }
+        }
+        SubCommand::ShowBuffers {
+            ref network,
+            close_all,
+            upgrade_authority,
+            dry_run,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            command::set_dry_run(dry_run);
+            let (config, _, _) = Config::discover(config_path)?;
+            let network_config = config.network_config(network)?;
+            let url = network_config
+                .url
+                .clone()
+                .unwrap_or_else(|| network.url().to_string());
+            let deployer_path = network_config.deployer.as_path_buf();
 
-            output_header("Switching to new buffer (please connect your wallet)");
+            // Upgrades create buffers with the deployer as authority before handing
+            // them over to the upgrade authority, so check both.
+            let mut authorities = vec![];
+            let deployer = solana_sdk::signer::keypair::read_keypair_file(&deployer_path)
+                .map_err(|_| {
+                    format_err!(
+                        "could not read deployer keypair {}",
+                        deployer_path.display()
+                    )
+                })?
+                .pubkey();
+            authorities.push(deployer);
+            match network_config.upgrade_authority_pubkey() {
+                Ok(authority) if authority != deployer => authorities.push(authority),
+                Ok(_) => {}
+                Err(err) => println!("{}", format!("WARNING: {}", err).yellow()),
+            }
 
-            command::exec(
-                Command::new("solana")
-                    .arg("--url")
-                    .arg(&workspace.network_url())
-                    .arg("--keypair")
-                    .arg(&upgrade_authority_keypair)
-                    .arg("program")
-                    .arg("deploy")
-                    .arg("--buffer")
-                    .arg(buffer_key.to_string())
-                    .arg("--program-id")
-                    .arg(workspace.program_key.to_string()),
-            )?;
+            let mut buffers = vec![];
+            for authority in &authorities {
+                buffers.extend(workspace::fetch_buffers(&url, authority)?);
+            }
+            if buffers.is_empty() {
+                println!("No buffers found.");
+                return Ok(());
+            }
 
-            workspace.show_program()?;
+            println!(
+                "{:<44}  {:<44} {:>10} {:>14}",
+                "BUFFER", "AUTHORITY", "SIZE", "SOL"
+            );
+            for buffer in &buffers {
+                println!(
+                    "{:<44}  {:<44} {:>10} {:>14}",
+                    buffer.address,
+                    buffer.authority,
+                    buffer.data_len,
+                    solana_sdk::native_token::lamports_to_sol(buffer.lamports)
+                );
+            }
 
-            if workspace.has_anchor() {
-                if skip_anchor_idl {
-                    output_header("Skipping Anchor IDL upload.");
-                } else {
-                    output_header("Uploading new IDL");
+            if close_all {
+                for buffer in &buffers {
+                    let authority_args = if buffer.authority == deployer.to_string() {
+                        vec![]
+                    } else if let Some(upgrade_authority) = &upgrade_authority {
+                        vec!["--authority", upgrade_authority.as_str()]
+                    } else {
+                        println!(
+                            "{}",
+                            format!(
+                                "Skipping {}: pass --upgrade-authority to close buffers owned by {}.",
+                                buffer.address, buffer.authority
+                            )
+                            .yellow()
+                        );
+                        continue;
+                    };
+                    output_header(output, "Closing buffer");
                     command::exec(
-                        anchor_cmd!(workspace, "idl")
-                            .arg("write-buffer")
-                            .arg(workspace.program_key.to_string())
-                            .arg("--filepath")
-                            .arg(&workspace.program_paths.idl),
+                        Command::new("solana")
+                            .arg("--url")
+                            .arg(&url)
+                            .arg("--commitment")
+                            .arg(command::commitment().to_string())
+                            .arg("--keypair")
+                            .arg(&deployer_path)
+                            .arg("program")
+                            .arg("close")
+                            .arg(&buffer.address)
+                            .args(authority_args),
                     )?;
+                }
+            }
+        }
+        SubCommand::Status {
+            version,
+            program,
+            ref network,
+        } => {
+            let network = &resolve_network(network.clone(), config_path, output)?;
+            let workspace =
+                &workspace::load(program.as_str(), version, network.clone(), config_path)?;
+            let info = workspace.fetch_program()?;
+            if output.is_json() {
+                let status = workspace::ProgramStatus::new(&program, workspace, &info);
+                return write_result(output, None, &status);
+            }
+            println!("Program: {}", program);
+            println!("    Address: {}", info.program_id);
+            println!("    Program data: {}", info.programdata_address);
+            println!("    Upgrade authority: {}", info.authority);
+            println!("    Data length: {} bytes", info.data_len);
+            println!("    Last deployed slot: {}", info.last_deploy_slot);
+            println!(
+                "    Balance: {} SOL",
+                solana_sdk::native_token::lamports_to_sol(info.lamports)
+            );
 
-                    println!(
-                        "WARNING: please manually run `anchor idl set-buffer {} --buffer <BUFFER>`",
-                        workspace.program_key.to_string()
-                    );
-                    println!("TODO: need to be able to hook into anchor for this");
+            warn_on_authority_mismatch(workspace, &info.authority);
+        }
+    }
+
+    Ok(())
+}
+
+/// Address of a program: the one declared in Captain.toml, or else the one of its
+/// program keypair for `version`, which defaults to the current version.
+fn program_address(
+    config: &Config,
+    program: &str,
+    root: &Path,
+    version: Option<Version>,
+) -> Result<Pubkey> {
+    if let Some(address) = config.programs.get(program).and_then(|p| p.address) {
+        return Ok(address);
+    }
+    let version = match version {
+        Some(version) => version,
+        None => workspace::current_version(config, program, root)?.0,
+    };
+    let kp_path = config.program_kp_path(&version, program);
+    Ok(solana_sdk::signer::keypair::read_keypair_file(&kp_path)
+        .map_err(|_| format_err!("could not read program keypair {}", kp_path.display()))?
+        .pubkey())
+}
+
+/// Requests an airdrop of `amount` SOL to `address`.
+fn airdrop(url: &str, address: &Pubkey, amount: f64, max_retries: u32) -> Result<()> {
+    command::exec_with_retries(
+        std::process::Command::new("solana")
+            .arg("--url")
+            .arg(url)
+            .arg("--commitment")
+            .arg(command::commitment().to_string())
+            .arg("airdrop")
+            .arg(amount.to_string())
+            .arg(address.to_string()),
+        max_retries,
+    )?;
+    Ok(())
+}
+
+/// Starts `solana-test-validator` with its ledger in `.captain/test-ledger/` and waits
+/// for it to become healthy at `url`. Returns `None` if a node is already running there.
+fn start_test_validator(
+    root: &Path,
+    url: &str,
+    reset: bool,
+    startup_timeout: u64,
+) -> Result<Option<std::process::Child>> {
+    if rpc::health(url).is_ok() {
+        println!("Using the validator already running at {}", url);
+        return Ok(None);
+    }
+    command::require("solana-test-validator")?;
+    let captain_dir = root.join(".captain");
+    std::fs::create_dir_all(&captain_dir)?;
+    let log_path = captain_dir.join("test-validator.log");
+    let log = File::create(&log_path)?;
+    println!(
+        "Starting solana-test-validator, logging to {}",
+        log_path.display()
+    );
+    let mut validator = Command::new("solana-test-validator")
+        .current_dir(root)
+        .arg("--ledger")
+        .arg(captain_dir.join("test-ledger"))
+        .args(reset.then_some("--reset"))
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .spawn()
+        .map_err(|e| format_err!("Error running solana-test-validator: {}", e))?;
+
+    let healthy = rpc::poll_until(
+        || {
+            if rpc::health(url).is_ok() {
+                return Ok(Some(()));
+            }
+            match validator.try_wait()? {
+                Some(status) => Err(anyhow!(
+                    "solana-test-validator exited with {}. See {}.",
+                    status,
+                    log_path.display()
+                )),
+                None => Ok(None),
+            }
+        },
+        rpc::Backoff {
+            initial: std::time::Duration::from_millis(250),
+            max: std::time::Duration::from_secs(2),
+        },
+        std::time::Duration::from_secs(startup_timeout),
+    )?;
+    if healthy.is_none() {
+        validator.kill()?;
+        validator.wait()?;
+        return Err(anyhow!(
+            "solana-test-validator was not healthy after {}s. See {}.",
+            startup_timeout,
+            log_path.display()
+        ));
+    }
+    Ok(Some(validator))
+}
+
+/// Outcome of a check of `captain doctor`.
+#[derive(Clone, Copy, PartialEq)]
+enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Prints the outcome of a check of `captain doctor`, returning its status.
+fn report(status: CheckStatus, message: &str) -> CheckStatus {
+    let label = match status {
+        CheckStatus::Pass => "[pass]".green(),
+        CheckStatus::Warn => "[warn]".yellow(),
+        CheckStatus::Fail => "[fail]".red(),
+    };
+    println!("{} {}", label, message);
+    status
+}
+
+/// Runs the checks of `captain doctor`, erroring if any of them failed.
+fn doctor(config_path: Option<&Path>, network: Option<Network>) -> Result<()> {
+    let mut statuses = vec![];
+
+    let config = Config::discover(config_path);
+    let anchor = match &config {
+        Ok((_, _, root)) => root.join("Anchor.toml").exists(),
+        Err(_) => false,
+    };
+    for (tool, required) in [("solana", true), ("cargo", true), ("anchor", anchor)] {
+        let status = match command::require(tool) {
+            Ok(()) => {
+                let version = command::exec_output(Command::new(tool).arg("--version"))
+                    .ok()
+                    .and_then(|output| output.lines().next().map(str::to_string))
+                    .filter(|version| !version.is_empty())
+                    .unwrap_or_else(|| "unknown version".to_string());
+                report(CheckStatus::Pass, &format!("{}: {}", tool, version))
+            }
+            Err(err) if required => report(CheckStatus::Fail, &err.to_string()),
+            Err(_) => report(
+                CheckStatus::Pass,
+                &format!(
+                    "{}: not installed, and not needed without Anchor.toml",
+                    tool
+                ),
+            ),
+        };
+        statuses.push(status);
+    }
+
+    let (config, root) = match config {
+        Ok((config, _, root)) => {
+            report(CheckStatus::Pass, "Captain.toml is valid");
+            (config, root)
+        }
+        Err(err) => {
+            report(CheckStatus::Fail, &format!("Captain.toml: {}", err));
+            return Err(anyhow!(
+                "Captain.toml is invalid, so nothing else was checked."
+            ));
+        }
+    };
+    if let Some(required) = &config.solana_version {
+        statuses.push(match workspace::check_solana_version(required) {
+            Ok(()) => report(
+                CheckStatus::Pass,
+                &format!("solana CLI matches solana_version {}", required),
+            ),
+            Err(err) => report(CheckStatus::Fail, &err.to_string()),
+        });
+    }
+
+    let network = network
+        .or_else(|| config.default_network.clone())
+        .unwrap_or(Network::Devnet);
+    match config.network_config(&network) {
+        Ok(network_config) => {
+            let url = network_config
+                .url
+                .clone()
+                .unwrap_or_else(|| network.url().to_string());
+            let rpc_status = match rpc::health(&url) {
+                Ok(()) => report(CheckStatus::Pass, &format!("RPC node {} is healthy", url)),
+                Err(err) => report(CheckStatus::Fail, &format!("RPC node {}: {}", url, err)),
+            };
+            statuses.push(rpc_status);
+
+            let deployer_path = network_config.deployer.as_path_buf();
+            match solana_sdk::signer::keypair::read_keypair_file(&deployer_path) {
+                Ok(deployer) => {
+                    statuses.push(report(
+                        CheckStatus::Pass,
+                        &format!(
+                            "Deployer {} ({}) is readable",
+                            deployer.pubkey(),
+                            deployer_path.display()
+                        ),
+                    ));
+                    // Without an RPC node the balance cannot be known.
+                    if rpc_status == CheckStatus::Pass {
+                        let status = match rpc::balance(
+                            &url,
+                            &deployer.pubkey().to_string(),
+                            command::commitment(),
+                        ) {
+                            Ok(0) => report(
+                                CheckStatus::Fail,
+                                &format!("Deployer has no SOL on {}", network),
+                            ),
+                            Ok(lamports) => report(
+                                CheckStatus::Pass,
+                                &format!(
+                                    "Deployer has {} SOL on {}",
+                                    solana_sdk::native_token::lamports_to_sol(lamports),
+                                    network
+                                ),
+                            ),
+                            Err(err) => report(
+                                CheckStatus::Fail,
+                                &format!("Could not fetch the deployer balance: {}", err),
+                            ),
+                        };
+                        statuses.push(status);
+                    }
                 }
+                Err(_) => statuses.push(report(
+                    CheckStatus::Fail,
+                    &format!(
+                        "Could not read deployer keypair {}",
+                        deployer_path.display()
+                    ),
+                )),
             }
+        }
+        Err(err) => statuses.push(report(CheckStatus::Fail, &err.to_string())),
+    }
 
-            println!("Deployment success!");
+    for program in workspace::list_source_programs(&root)? {
+        if config.is_excluded(&program) {
+            continue;
         }
+        let bin = root
+            .join("target")
+            .join("deploy")
+            .join(format!("{}.so", program));
+        statuses.push(if bin.exists() {
+            report(CheckStatus::Pass, &format!("{} is built", program))
+        } else {
+            report(
+                CheckStatus::Warn,
+                &format!("{} is not built yet. Run `captain build`.", program),
+            )
+        });
     }
 
+    let failed = statuses
+        .iter()
+        .filter(|status| **status == CheckStatus::Fail)
+        .count();
+    let warned = statuses
+        .iter()
+        .filter(|status| **status == CheckStatus::Warn)
+        .count();
+    if failed > 0 {
+        return Err(anyhow!("{} checks failed, {} warnings", failed, warned));
+    }
+    println!(
+        "{}",
+        format!("All checks passed, {} warnings.", warned).green()
+    );
     Ok(())
 }
 
-fn output_header(header: &'static str) {
-    println!();
-    println!("{}", "===================================".bold());
-    println!();
-    println!("    {}", header.bold());
-    println!();
-    println!("{}", "===================================".bold());
-    println!();
+/// Returns the network passed with `--network`, falling back to the `default_network`
+/// of Captain.toml and then to devnet.
+fn resolve_network(
+    network: Option<Network>,
+    config_path: Option<&Path>,
+    output: Output,
+) -> Result<Network> {
+    if let Some(network) = network {
+        return Ok(network);
+    }
+    let (config, _, _) = Config::discover(config_path)?;
+    let network = match config.default_network {
+        Some(network) => {
+            status!(
+                output,
+                "Using network {} (default_network in Captain.toml)",
+                network
+            );
+            network
+        }
+        None => {
+            status!(output, "Using network {} (default)", Network::Devnet);
+            Network::Devnet
+        }
+    };
+    Ok(network)
+}
+
+/// Logs warnings by default, and more of Captain's own messages with each `-v`.
+fn init_logger(verbose: u64) {
+    let filter = match verbose {
+        0 => "warn",
+        1 => "warn,captain=info",
+        2 => "warn,captain=debug",
+        _ => "trace",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(filter))
+        .format_timestamp(None)
+        .init();
 }
 
 fn main() {
-    if let Err(err) = main_with_result() {
-        println!("Error: {}", err);
+    let opts: Opts = Opts::parse();
+    // See https://no-color.org.
+    if std::env::var_os("NO_COLOR").is_some() {
+        colored::control::set_override(false);
+    }
+    init_logger(opts.verbose);
+    let output = opts.output;
+    if let Err(err) = main_with_result(opts) {
+        let categorized = err.downcast_ref::<error::CaptainError>();
+        if output == OutputFormat::Json {
+            println!(
+                "{}",
+                serde_json::json!({
+                    "success": false,
+                    "error": err.to_string(),
+                    "kind": categorized.map(error::CaptainError::kind),
+                    "hint": categorized.and_then(error::CaptainError::hint),
+                })
+            );
+        } else {
+            println!("Error: {}", err);
+            if let Some(hint) = categorized.and_then(error::CaptainError::hint) {
+                println!("{}", hint.yellow());
+            }
+        }
         std::process::exit(1);
     }
 }
@@ -0,0 +1,67 @@
+//! Upgrades through a multisig upgrade authority, such as a Squads vault, which cannot
+//! sign with the Solana CLI. Captain prepares the buffer and prints the instruction to
+//! propose to the multisig.
+use serde::Serialize;
+use solana_sdk::bpf_loader_upgradeable;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+
+#[derive(Debug, Serialize)]
+pub struct SerializedInstruction {
+    pub program_id: String,
+    pub accounts: Vec<SerializedAccount>,
+    /// Base58 encoded instruction data.
+    pub data: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SerializedAccount {
+    pub pubkey: String,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl From<Instruction> for SerializedInstruction {
+    fn from(instruction: Instruction) -> Self {
+        SerializedInstruction {
+            program_id: instruction.program_id.to_string(),
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| SerializedAccount {
+                    pubkey: meta.pubkey.to_string(),
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: solana_sdk::bs58::encode(&instruction.data).into_string(),
+        }
+    }
+}
+
+impl fmt::Display for SerializedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Program: {}", self.program_id)?;
+        writeln!(f, "Accounts:")?;
+        for account in &self.accounts {
+            writeln!(
+                f,
+                "    {} (signer: {}, writable: {})",
+                account.pubkey, account.is_signer, account.is_writable
+            )?;
+        }
+        write!(f, "Data (base58): {}", self.data)
+    }
+}
+
+/// The instruction upgrading `program` to `buffer`, signed by `authority`. The lamports
+/// of the buffer are returned to `spill`.
+pub fn upgrade_instruction(
+    program: &Pubkey,
+    buffer: &Pubkey,
+    authority: &Pubkey,
+    spill: &Pubkey,
+) -> SerializedInstruction {
+    bpf_loader_upgradeable::upgrade(program, buffer, authority, spill).into()
+}
@@ -0,0 +1,88 @@
+//! Printing results and prompting the user.
+use crate::config::Network;
+use anyhow::{anyhow, format_err, Result};
+use colored::*;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
+
+#[derive(Clone, Copy, Debug, Display, EnumString, EnumVariantNames, IntoStaticStr, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// How output is printed, as set by the global options.
+#[derive(Clone, Copy, Debug)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// Suppresses decorative headers.
+    pub quiet: bool,
+}
+
+impl Output {
+    pub fn is_json(self) -> bool {
+        self.format == OutputFormat::Json
+    }
+}
+
+/// Prints the result with `--output json`, and writes it to `summary_out` if given.
+pub fn write_result<T: Serialize>(
+    output: Output,
+    summary_out: Option<&Path>,
+    result: &T,
+) -> Result<()> {
+    if output.is_json() {
+        println!("{}", serde_json::to_string(result)?);
+    }
+    if let Some(path) = summary_out {
+        std::fs::write(path, serde_json::to_string_pretty(result)? + "\n")
+            .map_err(|e| format_err!("could not write summary to {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Asks a yes or no question, defaulting to no.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Requires the user to type the program name before anything is sent to mainnet.
+pub fn confirm_mainnet(program: &str, network: &Network, skip: bool) -> Result<()> {
+    if skip || *network != Network::Mainnet {
+        return Ok(());
+    }
+    print!(
+        "You are about to deploy {} to {}. Type the program name to confirm: ",
+        program, network
+    );
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    if input.trim() != program {
+        return Err(anyhow!(
+            "Confirmation did not match the program name. Aborting."
+        ));
+    }
+    Ok(())
+}
+
+/// Prints a banner announcing the next step, unless the output is JSON or quiet.
+pub fn output_header(output: Output, header: &str) {
+    if output.is_json() || output.quiet {
+        return;
+    }
+    println!();
+    println!("{}", "===================================".bold());
+    println!();
+    println!("    {}", header.bold());
+    println!();
+    println!("{}", "===================================".bold());
+    println!();
+}
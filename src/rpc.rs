@@ -0,0 +1,313 @@
+//! Minimal JSON-RPC client for the few queries the Solana CLI cannot answer.
+use anyhow::{anyhow, format_err, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::transaction::Transaction;
+use std::time::{Duration, Instant};
+use strum_macros::{Display, EnumString, EnumVariantNames, IntoStaticStr};
+
+/// How [wait_for_confirmations] backs off between signature status polls.
+const CONFIRMATION_BACKOFF: Backoff = Backoff {
+    initial: Duration::from_millis(500),
+    max: Duration::from_secs(5),
+};
+
+/// How settled a transaction must be, from least to most.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Display,
+    EnumString,
+    EnumVariantNames,
+    Eq,
+    IntoStaticStr,
+    Ord,
+    PartialEq,
+    PartialOrd,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithContext<T> {
+    value: T,
+}
+
+/// Status of a transaction, as returned by `getSignatureStatuses`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureStatus {
+    /// Number of blocks since the transaction was processed, or `None` once it is
+    /// finalized.
+    pub confirmations: Option<u64>,
+    /// Set if the transaction failed.
+    pub err: Option<Value>,
+    pub confirmation_status: Option<Commitment>,
+}
+
+fn call<T: DeserializeOwned>(url: &str, method: &str, params: Value) -> Result<T> {
+    log::debug!("Calling {} on {}", method, url);
+    let response: Response<T> = reqwest::blocking::Client::new()
+        .post(url)
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }))
+        .send()
+        .and_then(|response| response.json())
+        .map_err(|e| format_err!("{} request to {} failed: {}", method, url, e))?;
+    if let Some(error) = response.error {
+        return Err(anyhow!("{} request to {} failed: {}", method, url, error));
+    }
+    response
+        .result
+        .ok_or_else(|| format_err!("{} response from {} has no result", method, url))
+}
+
+/// Fetches the status of a transaction, or `None` if the node has not seen it.
+pub fn signature_status(url: &str, signature: &str) -> Result<Option<SignatureStatus>> {
+    let statuses: WithContext<Vec<Option<SignatureStatus>>> = call(
+        url,
+        "getSignatureStatuses",
+        json!([[signature], { "searchTransactionHistory": true }]),
+    )?;
+    Ok(statuses.value.into_iter().next().flatten())
+}
+
+/// Errors unless the node reports itself as healthy.
+pub fn health(url: &str) -> Result<()> {
+    let _: String = call(url, "getHealth", json!([]))?;
+    Ok(())
+}
+
+/// Fetches the balance of an account in lamports.
+pub fn balance(url: &str, address: &str, commitment: Commitment) -> Result<u64> {
+    let balance: WithContext<u64> = call(
+        url,
+        "getBalance",
+        json!([address, { "commitment": commitment.to_string() }]),
+    )?;
+    Ok(balance.value)
+}
+
+/// Blocks until the transaction has reached `commitment` and has at least
+/// `confirmations` confirmations or is finalized, erroring if it failed or `timeout`
+/// passed first.
+pub fn wait_for_confirmations(
+    url: &str,
+    signature: &str,
+    confirmations: u64,
+    commitment: Commitment,
+    timeout: Duration,
+) -> Result<()> {
+    let confirmed = poll_until(
+        || {
+            let status = match signature_status(url, signature)? {
+                Some(status) => status,
+                None => return Ok(None),
+            };
+            if let Some(err) = status.err {
+                return Err(anyhow!("transaction {} failed: {}", signature, err));
+            }
+            let committed = status
+                .confirmation_status
+                .is_some_and(|status| status >= commitment);
+            Ok(
+                (committed && status.confirmations.is_none_or(|c| c >= confirmations))
+                    .then_some(()),
+            )
+        },
+        CONFIRMATION_BACKOFF,
+        timeout,
+    )?;
+    confirmed.ok_or_else(|| {
+        format_err!(
+            "transaction {} did not reach {} confirmations within {}s",
+            signature,
+            confirmations,
+            timeout.as_secs()
+        )
+    })
+}
+
+/// Delays between the attempts of [poll_until] and of retried commands: `initial`
+/// after the first attempt, doubling after each one up to `max`.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Delay after attempt `attempt`, counting from 0, before jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+
+    /// [Backoff::delay] reduced by a random amount of up to half, so that concurrent
+    /// pollers spread out.
+    pub fn jittered_delay(&self, attempt: u32) -> Duration {
+        self.delay(attempt)
+            .mul_f64(1.0 - rand::random::<f64>() / 2.0)
+    }
+}
+
+/// Calls `f` until it returns `Some`, sleeping a jittered [Backoff] delay between
+/// attempts. Returns `None` if `timeout` passes first, so callers can say what they
+/// were waiting for, and an error as soon as `f` fails.
+pub fn poll_until<T>(
+    mut f: impl FnMut() -> Result<Option<T>>,
+    backoff: Backoff,
+    timeout: Duration,
+) -> Result<Option<T>> {
+    let started = Instant::now();
+    for attempt in 0.. {
+        if let Some(value) = f()? {
+            return Ok(Some(value));
+        }
+        let remaining = match timeout.checked_sub(started.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => remaining,
+            _ => break,
+        };
+        std::thread::sleep(backoff.jittered_delay(attempt).min(remaining));
+    }
+    Ok(None)
+}
+
+/// Result of `simulateTransaction`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Simulation {
+    /// Set if the transaction would fail.
+    pub err: Option<Value>,
+    pub logs: Option<Vec<String>>,
+    pub units_consumed: Option<u64>,
+}
+
+/// Simulates an unsigned transaction against the latest blockhash without sending it.
+pub fn simulate_transaction(
+    url: &str,
+    transaction: &Transaction,
+    commitment: Commitment,
+) -> Result<Simulation> {
+    let serialized = bincode::serialize(transaction)
+        .map_err(|e| format_err!("could not serialize transaction: {}", e))?;
+    let simulation: WithContext<Simulation> = call(
+        url,
+        "simulateTransaction",
+        json!([
+            base64::encode(serialized),
+            {
+                "encoding": "base64",
+                "sigVerify": false,
+                "replaceRecentBlockhash": true,
+                "commitment": commitment.to_string(),
+            }
+        ]),
+    )?;
+    Ok(simulation.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    const BACKOFF: Backoff = Backoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(500),
+    };
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(BACKOFF.delay(0), Duration::from_millis(100));
+        assert_eq!(BACKOFF.delay(1), Duration::from_millis(200));
+        assert_eq!(BACKOFF.delay(2), Duration::from_millis(400));
+        assert_eq!(BACKOFF.delay(3), BACKOFF.max);
+        assert_eq!(BACKOFF.delay(u32::MAX), BACKOFF.max);
+    }
+
+    #[test]
+    fn jitter_stays_within_half_of_delay() {
+        for attempt in 0..5 {
+            for _ in 0..100 {
+                let delay = BACKOFF.delay(attempt);
+                let jittered = BACKOFF.jittered_delay(attempt);
+                assert!(jittered <= delay);
+                assert!(jittered >= delay / 2);
+            }
+        }
+    }
+
+    const FAST: Backoff = Backoff {
+        initial: Duration::from_millis(1),
+        max: Duration::from_millis(5),
+    };
+
+    #[test]
+    fn poll_until_returns_first_value() {
+        let calls = Cell::new(0);
+        let value = poll_until(
+            || {
+                calls.set(calls.get() + 1);
+                Ok((calls.get() == 3).then_some(calls.get()))
+            },
+            FAST,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(value, Some(3));
+    }
+
+    #[test]
+    fn poll_until_times_out() {
+        let calls = Cell::new(0);
+        let started = Instant::now();
+        let value: Option<()> = poll_until(
+            || {
+                calls.set(calls.get() + 1);
+                Ok(None)
+            },
+            FAST,
+            Duration::from_millis(50),
+        )
+        .unwrap();
+        assert_eq!(value, None);
+        assert!(calls.get() > 1);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn poll_until_stops_on_error() {
+        let calls = Cell::new(0);
+        let result: Result<Option<()>> = poll_until(
+            || {
+                calls.set(calls.get() + 1);
+                Err(anyhow!("node is down"))
+            },
+            FAST,
+            Duration::from_secs(5),
+        );
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,52 @@
+//! Types for the JSON output of the Solana CLI.
+use serde::Deserialize;
+
+/// Output of `solana program show <program> --output json`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramInfo {
+    pub program_id: String,
+    pub programdata_address: String,
+    /// Upgrade authority, or `none` if the program is immutable.
+    pub authority: String,
+    pub last_deploy_slot: u64,
+    pub data_len: usize,
+    pub lamports: u64,
+}
+
+/// Output of `solana program deploy --output json`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgramDeployOutput {
+    pub program_id: String,
+    /// Signature of the final deploy transaction. Not printed by older CLI versions.
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+/// Output of `solana program show --buffers --output json`.
+#[derive(Debug, Deserialize)]
+pub struct BufferAccounts {
+    pub buffers: Vec<BufferInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferInfo {
+    pub address: String,
+    pub authority: String,
+    pub data_len: usize,
+    pub lamports: u64,
+}
+
+/// Output of `solana account <address> --output json`.
+#[derive(Debug, Deserialize)]
+pub struct AccountOutput {
+    pub account: AccountInfo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountInfo {
+    pub owner: String,
+    pub executable: bool,
+}
@@ -1,16 +1,46 @@
 use crate::command;
 use crate::config::ArtifactPaths;
+use crate::config::CaptainPath;
+use crate::config::Config;
+use crate::config::DirtyTreePolicy;
+use crate::config::Network;
 use crate::config::NetworkConfig;
-use crate::Config;
-use crate::Network;
+use crate::config::VersionSourceConfig;
+use crate::config::MAX_COMPUTE_UNIT_LIMIT;
+use crate::deployments;
+use crate::deployments::Deployment;
+use crate::error::AuthorityKind;
+use crate::error::CaptainError;
+use crate::keypair;
+use crate::rpc;
+use crate::solana::AccountOutput;
+use crate::solana::BufferAccounts;
+use crate::solana::BufferInfo;
+use crate::solana::ProgramDeployOutput;
+use crate::solana::ProgramInfo;
 use anyhow::{anyhow, format_err, Result};
 use cargo_toml::Manifest;
-use semver::Version;
+use colored::*;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::bpf_loader_upgradeable::UpgradeableLoaderState;
+use solana_sdk::native_token::lamports_to_sol;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
 use solana_sdk::signature::Signer;
+use solana_sdk::transaction::Transaction;
+use std::fmt;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Lamports added to cost estimates to cover transaction fees.
+const FEE_MARGIN_LAMPORTS: u64 = 10_000_000;
+
+/// How long to wait for a transaction to be confirmed.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// Deploys a program.
 pub struct Workspace {
@@ -18,11 +48,110 @@ pub struct Workspace {
     pub network: Network,
     pub deployer_path: PathBuf,
     pub deploy_version: Version,
+    pub version_source: VersionSource,
+    /// The version `version_source` resolves to, which `--version` may differ from.
+    pub current_version: Option<Version>,
     pub program_paths: ProgramPaths,
-    pub config: Config,
     pub network_config: NetworkConfig,
     pub artifact_paths: ArtifactPaths,
     pub program_key: Pubkey,
+    /// RPC URL passed on the command line, which replaces the network's URL.
+    pub network_url_override: Option<String>,
+    /// Label passed with `deploy --label`, recorded with the deployment.
+    pub label: Option<String>,
+    /// Keypair passed with `--fee-payer`, which pays for deploys and buffer writes
+    /// instead of the deployer.
+    pub fee_payer: Option<String>,
+    /// Additional signers passed with `--signer`, already validated.
+    pub signers: Vec<String>,
+    /// Git commit of the workspace, with a `-dirty` suffix if it has uncommitted
+    /// changes. `None` outside of a git repository.
+    pub commit: Option<String>,
+    pub dirty_tree: DirtyTreePolicy,
+    /// `max_program_size` from Captain.toml, or `--max-program-size`.
+    pub max_program_size: Option<u64>,
+    /// Supported versions of the Anchor CLI, from `anchor_version` in Captain.toml.
+    pub anchor_version: Option<VersionReq>,
+    /// Supported versions of the Solana CLI, from `solana_version` in Captain.toml.
+    pub solana_version: Option<VersionReq>,
+    pub pre_deploy: Option<String>,
+    pub post_deploy: Option<String>,
+}
+
+/// State of a deployed program, as printed by `captain status --output json`. The field
+/// names are a stable interface for monitoring: fields may be added, but are not renamed
+/// or removed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProgramStatus {
+    /// Name of the program in the workspace.
+    pub program: String,
+    /// Address of the program account.
+    pub program_key: String,
+    /// Upgrade authority, or `none` if the program is immutable.
+    pub authority: String,
+    /// Size of the program data account, in bytes.
+    pub data_len: usize,
+    /// Slot the program was last deployed or upgraded in.
+    pub last_deployed_slot: u64,
+    /// Whether `authority` is one of the upgrade authorities Captain.toml accepts for
+    /// the network.
+    pub matches_config: bool,
+}
+
+impl ProgramStatus {
+    pub fn new(program: &str, workspace: &Workspace, info: &ProgramInfo) -> Self {
+        let matches_config = workspace
+            .network_config
+            .accepted_upgrade_authorities()
+            .is_ok_and(|accepted| accepted.iter().any(|a| a.to_string() == info.authority));
+        ProgramStatus {
+            program: program.to_string(),
+            program_key: info.program_id.clone(),
+            authority: info.authority.clone(),
+            data_len: info.data_len,
+            last_deployed_slot: info.last_deploy_slot,
+            matches_config,
+        }
+    }
+}
+
+/// Shell commands configured in Captain.toml to run around deploys.
+#[derive(Clone, Copy, Debug)]
+pub enum Hook {
+    PreDeploy,
+    PostDeploy,
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hook::PreDeploy => write!(f, "pre_deploy"),
+            Hook::PostDeploy => write!(f, "post_deploy"),
+        }
+    }
+}
+
+/// Where the deploy version came from.
+pub enum VersionSource {
+    /// Passed with `--version`.
+    Flag,
+    /// The version of the program package in its Cargo.toml.
+    Manifest(PathBuf),
+    /// The latest git tag, with `version_source = "git-tag"`.
+    GitTag(String),
+    /// A version file, with `version_source = "file:<path>"`.
+    File(PathBuf),
+}
+
+impl fmt::Display for VersionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSource::Flag => write!(f, "--version"),
+            VersionSource::Manifest(path) => write!(f, "{}", path.display()),
+            VersionSource::GitTag(tag) => write!(f, "git tag {}", tag),
+            VersionSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
 }
 
 pub struct ProgramPaths {
@@ -31,11 +160,120 @@ pub struct ProgramPaths {
     pub id: PathBuf,
 }
 
-pub fn load(program: &str, version: Option<Version>, network: Network) -> Result<Workspace> {
-    let (config, _, root) = Config::discover()?;
+/// Paths passed on the command line which replace the ones discovered in the workspace.
+#[derive(Default)]
+pub struct ProgramOverrides {
+    /// A prebuilt program binary, used instead of `target/deploy/<program>.so`.
+    pub bin: Option<PathBuf>,
+    /// The program id, used instead of the keypair in `program_keypairs`.
+    pub id: Option<ProgramIdOverride>,
+}
+
+/// A program id passed on the command line, either as its keypair file or as its
+/// address.
+#[derive(Debug, Clone)]
+pub enum ProgramIdOverride {
+    Keypair(PathBuf),
+    /// The keypair is searched for in `program_keypairs` and `target/deploy/`.
+    Pubkey(Pubkey),
+}
+
+impl std::str::FromStr for ProgramIdOverride {
+    type Err = std::convert::Infallible;
 
-    let deploy_version = get_deploy_version(program, &root, version)?;
-    let program_paths = check_and_get_program_paths(&config, program, &root, &deploy_version)?;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(pubkey) => ProgramIdOverride::Pubkey(pubkey),
+            Err(_) => ProgramIdOverride::Keypair(PathBuf::from(s)),
+        })
+    }
+}
+
+impl ProgramIdOverride {
+    /// Path to the keypair of the program id.
+    fn keypair_path(&self, config: &Config, root: &Path) -> Result<PathBuf> {
+        let pubkey = match self {
+            ProgramIdOverride::Keypair(path) => return Ok(path.clone()),
+            ProgramIdOverride::Pubkey(pubkey) => pubkey,
+        };
+        let dirs = [
+            config.paths.program_keypairs.as_path_buf(),
+            root.join("target").join("deploy"),
+        ];
+        for dir in &dirs {
+            if !dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ex| ex.to_str()) != Some("json") {
+                    continue;
+                }
+                let matches = solana_sdk::signer::keypair::read_keypair_file(&path)
+                    .is_ok_and(|keypair| keypair.pubkey() == *pubkey);
+                if matches {
+                    return Ok(path);
+                }
+            }
+        }
+        Err(anyhow!(
+            "No keypair for program id {} found in {} or {}. Pass the path to its keypair to --program-id instead.",
+            pubkey,
+            dirs[0].display(),
+            dirs[1].display()
+        ))
+    }
+}
+
+pub fn load(
+    program: &str,
+    version: Option<Version>,
+    network: Network,
+    config_path: Option<&Path>,
+) -> Result<Workspace> {
+    load_with_overrides(
+        program,
+        version,
+        network,
+        config_path,
+        &ProgramOverrides::default(),
+    )
+}
+
+pub fn load_with_overrides(
+    program: &str,
+    version: Option<Version>,
+    network: Network,
+    config_path: Option<&Path>,
+    overrides: &ProgramOverrides,
+) -> Result<Workspace> {
+    let (config, _, root) = Config::discover(config_path)?;
+
+    if let Some(program_config) = config.programs.get(program) {
+        if !program_config.networks.is_empty() && !program_config.networks.contains(&network) {
+            return Err(anyhow!(
+                "Program {} may not be deployed to {}: Captain.toml only allows {}",
+                program,
+                network,
+                program_config
+                    .networks
+                    .iter()
+                    .map(|network| network.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+
+    let (deploy_version, version_source) = get_deploy_version(&config, program, &root, version)?;
+    let current_version = match version_source {
+        VersionSource::Flag => current_version(&config, program, &root)
+            .ok()
+            .map(|(version, _)| version),
+        _ => Some(deploy_version.clone()),
+    };
+    let program_paths =
+        check_and_get_program_paths(&config, program, &root, &deploy_version, overrides)?;
 
     let network_config = config.network_config(&network)?;
     let deployer_path = network_config.deployer.as_path_buf();
@@ -55,16 +293,59 @@ pub fn load(program: &str, version: Option<Version>, network: Network) -> Result
         .map_err(|_| format_err!("could not read kp file {}", program_id_path_display))?
         .pubkey();
 
+    if let Some(expected) = config.programs.get(program).and_then(|p| p.address) {
+        if expected != program_key {
+            return Err(anyhow!(
+                "Program {} is declared with address {} in Captain.toml, but its keypair {} is for {}",
+                program,
+                expected,
+                program_id_path_display,
+                program_key
+            ));
+        }
+    }
+
+    log::info!(
+        "Resolved {} {} (version from {}): binary {}, IDL {}, program keypair {}, deployer {}, artifacts {}",
+        program,
+        deploy_version,
+        version_source,
+        program_paths.bin.display(),
+        program_paths.idl.display(),
+        program_id_path_display,
+        deployer_path.display(),
+        artifact_paths.root.display()
+    );
+
+    let commit = git_commit(&root);
     Ok(Workspace {
-        config: config.clone(),
         network,
         root,
         network_config: network_config.clone(),
         deployer_path,
         deploy_version,
+        version_source,
+        current_version,
         program_paths,
         artifact_paths,
         program_key,
+        network_url_override: None,
+        label: None,
+        fee_payer: None,
+        signers: vec![],
+        commit,
+        dirty_tree: config.dirty_tree.unwrap_or(DirtyTreePolicy::Warn),
+        max_program_size: config.max_program_size,
+        anchor_version: config.anchor_version.clone(),
+        solana_version: config.solana_version.clone(),
+        pre_deploy: network_config
+            .pre_deploy
+            .clone()
+            .or_else(|| config.pre_deploy.clone()),
+        post_deploy: network_config
+            .post_deploy
+            .clone()
+            .or_else(|| config.post_deploy.clone()),
     })
 }
 
@@ -73,33 +354,87 @@ fn check_and_get_program_paths(
     program: &str,
     root: &Path,
     deploy_version: &Version,
+    overrides: &ProgramOverrides,
 ) -> Result<ProgramPaths> {
-    let program_bin_path = root
-        .join("target")
-        .join("deploy")
-        .join(format!("{}.so", program));
-    let program_idl_path = root
-        .join("target")
-        .join("idl")
-        .join(format!("{}.json", program));
-    let program_id_path = config.program_kp_path(deploy_version, program);
+    let program_idl_path = config
+        .programs
+        .get(program)
+        .and_then(|program_config| program_config.idl.as_ref())
+        .map(CaptainPath::as_path_buf)
+        .unwrap_or_else(|| {
+            root.join("target")
+                .join("idl")
+                .join(format!("{}.json", program))
+        });
+    let id_override = overrides
+        .id
+        .as_ref()
+        .map(|id| id.keypair_path(config, root))
+        .transpose()?;
+    if let (Some(bin), Some(id)) = (&overrides.bin, &id_override) {
+        // A prebuilt program may not come with the rest of the build directory.
+        for path in &[bin, id] {
+            if !path.exists() {
+                return Err(anyhow!("{} does not exist", path.display()));
+            }
+        }
+        return Ok(ProgramPaths {
+            bin: bin.clone(),
+            idl: program_idl_path,
+            id: id.clone(),
+        });
+    }
+
+    let program_bin_path = overrides.bin.clone().unwrap_or_else(|| {
+        root.join("target")
+            .join("deploy")
+            .join(format!("{}.so", program))
+    });
+    let program_id_path =
+        id_override.unwrap_or_else(|| config.program_kp_path(deploy_version, program));
 
     if !program_bin_path.exists() {
-        return Err(anyhow!(
-            "Program bin path {} does not exist",
-            program_bin_path.display()
-        ));
+        let candidates = if overrides.bin.is_none() {
+            similar_programs(program, root)?
+        } else {
+            vec![]
+        };
+        return Err(CaptainError::ProgramNotFound {
+            kind: "bin",
+            path: program_bin_path,
+            similar: candidates,
+        }
+        .into());
     }
     if !program_idl_path.exists() {
-        return Err(anyhow!(
-            "Program idl path {} does not exist",
-            program_idl_path.display()
-        ));
+        return Err(CaptainError::ProgramNotFound {
+            kind: "idl",
+            path: program_idl_path,
+            similar: vec![],
+        }
+        .into());
     }
     if !program_id_path.exists() {
+        let build_keypair = root
+            .join("target")
+            .join("deploy")
+            .join(format!("{}-keypair.json", program));
+        let hint = if build_keypair.exists() {
+            format!(
+                "Copy {} there to use the address generated by the build",
+                build_keypair.display()
+            )
+        } else {
+            format!(
+                "Generate one with `solana-keygen new -o {}`",
+                program_id_path.display()
+            )
+        };
         return Err(anyhow!(
-            "Program id path {} does not exist",
-            program_id_path.display()
+            "Program {} is built, but its program id keypair {} does not exist. {}.",
+            program,
+            program_id_path.display(),
+            hint
         ));
     }
 
@@ -110,35 +445,371 @@ fn check_and_get_program_paths(
     })
 }
 
-pub fn get_program_version(program: &str, root: &Path) -> Result<Version> {
-    let mf_path = &root.join("programs").join(program).join("Cargo.toml");
-    let program_manifest_path = if mf_path.exists() {
-        mf_path.clone()
-    } else {
-        root.join("programs")
-            .join(&program.replace("_", "-"))
-            .join("Cargo.toml")
+/// Names of all programs built into `target/deploy/`.
+pub fn list_programs(root: &Path) -> Result<Vec<String>> {
+    let mut programs = vec![];
+    for entry in fs::read_dir(root.join("target").join("deploy"))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ex| ex.to_str()) != Some("so") {
+            continue;
+        }
+        let program = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format_err!("invalid program file name {}", path.display()))?;
+        programs.push(program.to_string());
+    }
+    programs.sort();
+    Ok(programs)
+}
+
+/// Programs in `target/deploy/` whose names differ from `program` only in case or in
+/// dashes versus underscores.
+fn similar_programs(program: &str, root: &Path) -> Result<Vec<String>> {
+    let normalize = |name: &str| name.replace("-", "_").to_lowercase();
+    if !root.join("target").join("deploy").exists() {
+        return Ok(vec![]);
+    }
+    Ok(list_programs(root)?
+        .into_iter()
+        .filter(|candidate| candidate != program && normalize(candidate) == normalize(program))
+        .collect())
+}
+
+/// Names of all programs with a Cargo.toml under `programs/`, whether or not they
+/// have been built. Dashes in package names are replaced with underscores, as in
+/// the name of the built binary.
+pub fn list_source_programs(root: &Path) -> Result<Vec<String>> {
+    let programs_dir = root.join("programs");
+    if !programs_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut programs = vec![];
+    for entry in fs::read_dir(programs_dir)? {
+        let manifest_path = entry?.path().join("Cargo.toml");
+        if !manifest_path.exists() {
+            continue;
+        }
+        if let Some(package) = Manifest::from_path(&manifest_path)?.package {
+            programs.push(package.name.replace("-", "_"));
+        }
+    }
+    programs.sort();
+    Ok(programs)
+}
+
+/// Path to the Cargo.toml of a program, which lives in `programs/<program>/`. The
+/// directory may use dashes where the program name has underscores.
+pub fn get_program_manifest_path(program: &str, root: &Path) -> Result<PathBuf> {
+    let mf_path = root.join("programs").join(program).join("Cargo.toml");
+    if mf_path.exists() {
+        return Ok(mf_path);
+    }
+    let dashed_path = root
+        .join("programs")
+        .join(program.replace("_", "-"))
+        .join("Cargo.toml");
+    if dashed_path.exists() {
+        return Ok(dashed_path);
+    }
+    Err(CaptainError::ProgramNotFound {
+        kind: "manifest",
+        path: mf_path,
+        similar: vec![],
+    }
+    .into())
+}
+
+/// The most recently modified `.rs` file next to the Cargo.toml of the program.
+pub fn newest_source_file(program: &str, root: &Path) -> Result<Option<(PathBuf, SystemTime)>> {
+    fn visit(dir: &Path, newest: &mut Option<(PathBuf, SystemTime)>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name().and_then(|name| name.to_str()) != Some("target") {
+                    visit(&path, newest)?;
+                }
+            } else if path.extension().and_then(|ex| ex.to_str()) == Some("rs") {
+                let modified = fs::metadata(&path)?.modified()?;
+                if newest.as_ref().is_none_or(|(_, time)| modified > *time) {
+                    *newest = Some((path, modified));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let manifest_path = get_program_manifest_path(program, root)?;
+    let mut newest = None;
+    if let Some(dir) = manifest_path.parent() {
+        visit(dir, &mut newest)?;
+    }
+    Ok(newest)
+}
+
+/// The commit `HEAD` of the git repository containing `root` points to, with a `-dirty`
+/// suffix if there are uncommitted changes. `None` if `root` is not in a repository.
+pub fn git_commit(root: &Path) -> Option<String> {
+    let git = |args: &[&str]| {
+        std::process::Command::new("git")
+            .current_dir(root)
+            .args(args)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
     };
-    let program_manifest = Manifest::from_path(&program_manifest_path).map_err(|_| {
+    let commit = git(&["rev-parse", "HEAD"])?;
+    let dirty = git(&["status", "--porcelain"]).is_none_or(|status| !status.is_empty());
+    Some(if dirty {
+        format!("{}-dirty", commit)
+    } else {
+        commit
+    })
+}
+
+/// Whether any file in the package of each program changed since the git ref `since`,
+/// including uncommitted changes and new files which are not ignored.
+pub fn changed_since(root: &Path, since: &str, programs: &[String]) -> Result<Vec<bool>> {
+    let diff = command::exec_output(
+        std::process::Command::new("git")
+            .current_dir(root)
+            .arg("diff")
+            .arg("--name-only")
+            .arg("--relative")
+            // So that a file moved between programs counts for both of them.
+            .arg("--no-renames")
+            .arg(since)
+            .arg("--"),
+    )?;
+    // `git diff` does not list files which git does not track yet.
+    let untracked = command::exec_output(
+        std::process::Command::new("git")
+            .current_dir(root)
+            .arg("ls-files")
+            .arg("--others")
+            .arg("--exclude-standard"),
+    )?;
+    let changed: Vec<PathBuf> = diff
+        .lines()
+        .chain(untracked.lines())
+        .map(|line| root.join(line))
+        .collect();
+    programs
+        .iter()
+        .map(|program| {
+            let dirs = match get_program_manifest_path(program, root) {
+                Ok(manifest_path) => vec![manifest_path.parent().unwrap_or(root).to_path_buf()],
+                // The package of a program built before is gone, so look for its
+                // deleted files instead.
+                Err(_) => vec![
+                    root.join("programs").join(program),
+                    root.join("programs").join(program.replace("_", "-")),
+                ],
+            };
+            Ok(changed
+                .iter()
+                .any(|path| dirs.iter().any(|dir| path.starts_with(dir))))
+        })
+        .collect()
+}
+
+pub fn get_program_version(program: &str, root: &Path) -> Result<Version> {
+    let manifest_path = get_program_manifest_path(program, root)?;
+    let program_manifest = Manifest::from_path(&manifest_path)?;
+    let version = program_manifest
+        .package
+        .ok_or_else(|| anyhow!("{} has no [package]", manifest_path.display()))?
+        .version;
+    Version::parse(&version).map_err(|e| {
         format_err!(
-            "Program Cargo.toml not found at paths {} or {}",
-            &mf_path.display(),
-            &program_manifest_path.display()
+            "could not parse version `{}` in {} as semver: {}",
+            version,
+            manifest_path.display(),
+            e
         )
-    })?;
-    Ok(Version::parse(
-        program_manifest
-            .package
-            .ok_or_else(|| anyhow!("invalid package"))?
-            .version
-            .as_str(),
-    )?)
+    })
+}
+
+/// Hex-encoded SHA-256 of the given bytes.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
 }
 
-fn get_deploy_version(program: &str, root: &Path, version: Option<Version>) -> Result<Version> {
+/// Reads the hash from a `.sha256` file written by [Workspace::copy_artifacts], if it exists.
+pub fn read_sha256(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)?;
+    Ok(contents.split_whitespace().next().map(str::to_string))
+}
+
+/// Strips the zero padding at the end of a dumped program data account so that it
+/// can be compared against the local binary.
+pub fn trim_program_data<'a>(onchain: &'a [u8], local: &[u8]) -> &'a [u8] {
+    if onchain.len() > local.len() && onchain[local.len()..].iter().all(|b| *b == 0) {
+        &onchain[..local.len()]
+    } else {
+        onchain
+    }
+}
+
+/// Version of the installed Solana CLI, from `solana --version`, e.g.
+/// `solana-cli 1.18.26 (src:...)`.
+pub fn solana_version() -> Option<Version> {
+    let output =
+        command::exec_output(std::process::Command::new("solana").arg("--version")).ok()?;
+    output
+        .split_whitespace()
+        .nth(1)
+        .and_then(|version| Version::parse(version).ok())
+}
+
+/// Errors unless the installed Solana CLI matches `required`.
+pub fn check_solana_version(required: &VersionReq) -> Result<()> {
+    match solana_version() {
+        Some(installed) if required.matches(&installed) => Ok(()),
+        Some(installed) => Err(anyhow!(
+            "solana-cli {} is installed, but Captain.toml requires solana_version {}. Install a matching version, or pass --skip-version-check.",
+            installed,
+            required
+        )),
+        None => Err(anyhow!(
+            "could not determine the version of the Solana CLI, which Captain.toml requires to be {}. Pass --skip-version-check to deploy anyway.",
+            required
+        )),
+    }
+}
+
+/// Errors unless `dest` is a readable, non-empty copy of `source` of the same size, in
+/// case the copy failed without `cp` reporting it.
+fn check_copied(source: &Path, dest: &Path) -> Result<()> {
+    let copied = fs::metadata(dest)
+        .map_err(|e| format_err!("artifact {} was not copied: {}", dest.display(), e))?;
+    fs::File::open(dest)
+        .map_err(|e| format_err!("artifact {} is not readable: {}", dest.display(), e))?;
+    let expected = fs::metadata(source)?.len();
+    if copied.len() == 0 || copied.len() != expected {
+        return Err(anyhow!(
+            "artifact {} is {} bytes, but {} is {} bytes",
+            dest.display(),
+            copied.len(),
+            source.display(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the buffer address out of the output of `anchor idl write-buffer`.
+pub fn parse_idl_buffer(output: &str) -> Result<Pubkey> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Idl buffer created:"))
+        .and_then(|buffer| buffer.trim().parse().ok())
+        .ok_or_else(|| format_err!("could not find the IDL buffer address in the anchor output"))
+}
+
+/// Fetches the buffer accounts whose authority is `authority`.
+pub fn fetch_buffers(url: &str, authority: &Pubkey) -> Result<Vec<BufferInfo>> {
+    let output = command::exec_output(
+        std::process::Command::new("solana")
+            .arg("--url")
+            .arg(url)
+            .arg("--commitment")
+            .arg(command::commitment().to_string())
+            .arg("program")
+            .arg("show")
+            .arg("--buffers")
+            .arg("--buffer-authority")
+            .arg(authority.to_string())
+            .arg("--output")
+            .arg("json"),
+    )?;
+    let accounts: BufferAccounts = serde_json::from_str(&output)
+        .map_err(|e| format_err!("could not parse buffers of {}: {}", authority, e))?;
+    Ok(accounts.buffers)
+}
+
+/// Estimates the lamports needed to deploy a program of `program_len` bytes for the
+/// first time: the program account, a program data account twice the size of the
+/// program (the Solana CLI default), and the buffer the program is written to.
+pub fn estimate_deploy_cost(program_len: usize) -> Result<u64> {
+    let rent = Rent::default();
+    let program = rent
+        .minimum_balance(UpgradeableLoaderState::program_len().map_err(|e| format_err!("{}", e))?);
+    let programdata = rent.minimum_balance(
+        UpgradeableLoaderState::programdata_len(program_len * 2)
+            .map_err(|e| format_err!("{}", e))?,
+    );
+    Ok(program + programdata + estimate_upgrade_cost(program_len)?)
+}
+
+/// Estimates the lamports needed to write a program of `program_len` bytes into a buffer.
+pub fn estimate_upgrade_cost(program_len: usize) -> Result<u64> {
+    let buffer = Rent::default().minimum_balance(
+        UpgradeableLoaderState::buffer_len(program_len).map_err(|e| format_err!("{}", e))?,
+    );
+    Ok(buffer + FEE_MARGIN_LAMPORTS)
+}
+
+fn get_deploy_version(
+    config: &Config,
+    program: &str,
+    root: &Path,
+    version: Option<Version>,
+) -> Result<(Version, VersionSource)> {
     match version {
-        Some(v) => Ok(v),
-        None => get_program_version(program, root),
+        Some(v) => Ok((v, VersionSource::Flag)),
+        None => current_version(config, program, root),
+    }
+}
+
+/// The current version of the program according to `version_source` in Captain.toml.
+pub fn current_version(
+    config: &Config,
+    program: &str,
+    root: &Path,
+) -> Result<(Version, VersionSource)> {
+    match config
+        .version_source
+        .as_ref()
+        .unwrap_or(&VersionSourceConfig::Cargo)
+    {
+        VersionSourceConfig::Cargo => Ok((
+            get_program_version(program, root)?,
+            VersionSource::Manifest(get_program_manifest_path(program, root)?),
+        )),
+        VersionSourceConfig::GitTag => {
+            let tag = command::exec_output(
+                std::process::Command::new("git")
+                    .current_dir(root)
+                    .arg("describe")
+                    .arg("--tags")
+                    .arg("--abbrev=0"),
+            )?
+            .trim()
+            .to_string();
+            let version = Version::parse(tag.strip_prefix('v').unwrap_or(&tag))
+                .map_err(|e| format_err!("could not parse git tag `{}` as semver: {}", tag, e))?;
+            Ok((version, VersionSource::GitTag(tag)))
+        }
+        VersionSourceConfig::File(path) => {
+            let path = root.join(path);
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| format_err!("could not read {}: {}", path.display(), e))?;
+            let version = Version::parse(contents.trim()).map_err(|e| {
+                format_err!(
+                    "could not parse version `{}` in {} as semver: {}",
+                    contents.trim(),
+                    path.display(),
+                    e
+                )
+            })?;
+            Ok((version, VersionSource::File(path)))
+        }
     }
 }
 
@@ -153,6 +824,233 @@ impl Workspace {
         Ok(exit.status.success())
     }
 
+    /// Fetches the on-chain state of the program.
+    pub fn fetch_program(&self) -> Result<ProgramInfo> {
+        let output = command::exec_output(
+            solana_cmd!(self)
+                .arg("program")
+                .arg("show")
+                .arg(self.program_key.to_string())
+                .arg("--output")
+                .arg("json"),
+        )?;
+        serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse program {}: {}", self.program_key, e))
+    }
+
+    /// Errors unless the program account is executable and owned by the upgradeable
+    /// loader, i.e. the deploy went all the way through. Does nothing in dry run mode.
+    pub fn check_deployed(&self) -> Result<()> {
+        if command::is_dry_run() {
+            return Ok(());
+        }
+        let output = command::exec_output(
+            solana_cmd!(self)
+                .arg("account")
+                .arg(self.program_key.to_string())
+                .arg("--output")
+                .arg("json"),
+        )?;
+        let account: AccountOutput = serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse account {}: {}", self.program_key, e))?;
+        let loader = solana_sdk::bpf_loader_upgradeable::id().to_string();
+        if account.account.owner != loader {
+            return Err(anyhow!(
+                "program account {} is owned by {}, not the upgradeable loader {}",
+                self.program_key,
+                account.account.owner,
+                loader
+            ));
+        }
+        if !account.account.executable {
+            return Err(anyhow!(
+                "program account {} is not executable",
+                self.program_key
+            ));
+        }
+        Ok(())
+    }
+
+    /// Blocks until the transaction has `confirmations` confirmations.
+    pub fn wait_for_confirmations(&self, signature: &str, confirmations: u64) -> Result<()> {
+        rpc::wait_for_confirmations(
+            &self.network_url(),
+            signature,
+            confirmations,
+            command::commitment(),
+            CONFIRMATION_TIMEOUT,
+        )
+    }
+
+    /// Fetches the on-chain Anchor IDL of the program.
+    pub fn fetch_idl(&self) -> Result<serde_json::Value> {
+        let output = command::exec_output(
+            anchor_cmd!(self, "idl")
+                .arg("fetch")
+                .arg(self.program_key.to_string()),
+        )?;
+        serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse IDL of {}: {}", self.program_key, e))
+    }
+
+    /// Downloads the on-chain program data to the given path.
+    pub fn dump_program(&self, path: &Path) -> Result<()> {
+        command::exec_output(
+            solana_cmd!(self)
+                .arg("program")
+                .arg("dump")
+                .arg(self.program_key.to_string())
+                .arg(path),
+        )?;
+        Ok(())
+    }
+
+    /// Errors if the binary is larger than `max_program_size`, since rent grows with the
+    /// size of the program.
+    pub fn check_program_size(&self, bin: &Path) -> Result<()> {
+        let max_program_size = match self.max_program_size {
+            Some(max_program_size) => max_program_size,
+            None => return Ok(()),
+        };
+        let size = fs::metadata(bin)?.len();
+        if size > max_program_size {
+            return Err(anyhow!(
+                "{} is {} bytes, which exceeds max_program_size of {} bytes",
+                bin.display(),
+                size,
+                max_program_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Warns, or errors with `dirty_tree = "error"`, if deploying to mainnet from a git
+    /// tree with uncommitted changes, since the deployment could not be traced back to
+    /// its sources.
+    pub fn check_dirty_tree(&self) -> Result<()> {
+        let commit = match &self.commit {
+            Some(commit) if self.network == Network::Mainnet && commit.ends_with("-dirty") => {
+                commit
+            }
+            _ => return Ok(()),
+        };
+        let message = format!(
+            "the workspace has uncommitted changes on top of {}, so this deployment cannot be traced back to a commit",
+            commit.trim_end_matches("-dirty")
+        );
+        if self.dirty_tree == DirtyTreePolicy::Error {
+            return Err(anyhow!("{}. Commit them first.", message));
+        }
+        eprintln!("{}", format!("WARNING: {}", message).yellow());
+        Ok(())
+    }
+
+    /// Warns, or errors if `strict`, if a source file of the program was modified after
+    /// its binary was built. Only checked for the current version of the program, since
+    /// older versions were built from older sources.
+    pub fn check_stale(&self, program: &str, strict: bool) -> Result<()> {
+        if self.current_version.as_ref() != Some(&self.deploy_version) {
+            return Ok(());
+        }
+        let built = fs::metadata(&self.program_paths.bin)?.modified()?;
+        if let Some((path, modified)) = newest_source_file(program, &self.root)? {
+            if modified > built {
+                let message = format!(
+                    "{} was modified after {} was built, so the binary may be stale. Run `captain build` first.",
+                    path.display(),
+                    self.program_paths.bin.display()
+                );
+                if strict {
+                    return Err(anyhow!(message));
+                }
+                eprintln!("{}", format!("WARNING: {}", message).yellow());
+            }
+        }
+        Ok(())
+    }
+
+    /// Errors if the Solana CLI, or the Anchor CLI if `anchor`, is not installed, or if
+    /// the Solana CLI is not a `solana_version` the workspace supports.
+    pub fn require_cli(&self, anchor: bool) -> Result<()> {
+        command::require("solana")?;
+        if let Some(required) = &self.solana_version {
+            if !command::skip_version_check() {
+                check_solana_version(required)?;
+            }
+        }
+        if anchor && self.has_anchor() {
+            command::require("anchor")?;
+        }
+        Ok(())
+    }
+
+    /// Runs the hook, if configured, from the workspace root with the program, its
+    /// version and the network in `CAPTAIN_PROGRAM`, `CAPTAIN_VERSION` and
+    /// `CAPTAIN_NETWORK`.
+    pub fn run_hook(&self, hook: Hook, program: &str) -> Result<()> {
+        let script = match hook {
+            Hook::PreDeploy => &self.pre_deploy,
+            Hook::PostDeploy => &self.post_deploy,
+        };
+        let script = match script {
+            Some(script) => script,
+            None => return Ok(()),
+        };
+        command::exec(
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .current_dir(&self.root)
+                .env("CAPTAIN_PROGRAM", program)
+                .env("CAPTAIN_PROGRAM_ID", self.program_key.to_string())
+                .env("CAPTAIN_VERSION", self.deploy_version.to_string())
+                .env("CAPTAIN_NETWORK", self.network.to_string()),
+        )
+        .map_err(|e| format_err!("{} hook failed: {}", hook, e))
+    }
+
+    /// Warns, or errors if `strict`, if the installed Anchor CLI is not one of the
+    /// `anchor_version`s the workspace supports.
+    pub fn check_anchor_version(&self, strict: bool) -> Result<()> {
+        let required = match &self.anchor_version {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+        let installed = command::exec_output(std::process::Command::new("anchor").arg("--version"))
+            .ok()
+            .and_then(|output| {
+                output
+                    .split_whitespace()
+                    .last()
+                    .and_then(|version| Version::parse(version).ok())
+            });
+        let message = match installed {
+            Some(installed) if required.matches(&installed) => return Ok(()),
+            Some(installed) => format!(
+                "anchor-cli {} is installed, but Captain.toml requires anchor_version {}",
+                installed, required
+            ),
+            None => format!(
+                "could not determine the version of the Anchor CLI, which Captain.toml requires to be {}",
+                required
+            ),
+        };
+        if strict {
+            return Err(anyhow!(message));
+        }
+        eprintln!("{}", format!("WARNING: {}", message).yellow());
+        Ok(())
+    }
+
+    /// Returns true if the on-chain program is identical to `bin`.
+    pub fn is_up_to_date(&self, bin: &Path) -> Result<bool> {
+        let dump_file = tempfile::NamedTempFile::new()?;
+        self.dump_program(dump_file.path())?;
+        let local = fs::read(bin)?;
+        let onchain = fs::read(dump_file.path())?;
+        Ok(trim_program_data(&onchain, &local) == local.as_slice())
+    }
+
     pub fn copy_artifacts(&self) -> Result<()> {
         command::exec(
             std::process::Command::new("cp")
@@ -164,6 +1062,314 @@ impl Workspace {
                 .arg(&self.program_paths.idl)
                 .arg(&self.artifact_paths.idl),
         )?;
+        check_copied(&self.program_paths.bin, &self.artifact_paths.bin)?;
+        check_copied(&self.program_paths.idl, &self.artifact_paths.idl)?;
+        let bin = fs::read(&self.artifact_paths.bin)?;
+        if bin != fs::read(&self.program_paths.bin)? {
+            return Err(anyhow!(
+                "artifact {} does not match {}",
+                self.artifact_paths.bin.display(),
+                self.program_paths.bin.display()
+            ));
+        }
+        fs::write(
+            &self.artifact_paths.sha256,
+            format!("{}  program.so\n", sha256_hex(&bin)),
+        )?;
+        Ok(())
+    }
+
+    /// Appends a successful deployment of the program to the deployment log. Nothing is
+    /// recorded in dry run mode.
+    pub fn record_deployment(
+        &self,
+        program: &str,
+        buffer: Option<Pubkey>,
+        signature: Option<String>,
+    ) -> Result<()> {
+        if command::is_dry_run() {
+            return Ok(());
+        }
+        deployments::append(
+            &self.root,
+            Deployment {
+                timestamp: chrono::Utc::now(),
+                program: program.to_string(),
+                version: self.deploy_version.to_string(),
+                network: self.network_label(),
+                program_key: self.program_key.to_string(),
+                buffer: buffer.map(|b| b.to_string()),
+                signature,
+                commit: self.commit.clone(),
+                label: self.label.clone(),
+            },
+        )
+    }
+
+    /// Copies the deployed binary next to the released artifacts as
+    /// `<program>-<version>-<label>.so`, so it can be told apart from the others.
+    pub fn save_labeled_artifact(&self, program: &str, bin: &Path) -> Result<Option<PathBuf>> {
+        let label = match &self.label {
+            Some(label) if !command::is_dry_run() => label,
+            _ => return Ok(None),
+        };
+        let path = self
+            .artifact_paths
+            .root
+            .join(format!("{}-{}-{}.so", program, self.deploy_version, label));
+        fs::copy(bin, &path)?;
+        check_copied(bin, &path)?;
+        Ok(Some(path))
+    }
+
+    /// Parses the signature out of the output of `solana program deploy --output json`.
+    /// Returns `None` in dry run mode or if the CLI did not print one.
+    pub fn parse_deploy_signature(&self, output: &str) -> Result<Option<String>> {
+        if command::is_dry_run() {
+            return Ok(None);
+        }
+        let json = output
+            .find('{')
+            .map(|start| &output[start..])
+            .ok_or_else(|| format_err!("could not find the deploy output in {}", output.trim()))?;
+        let deploy: ProgramDeployOutput = serde_json::from_str(json)
+            .map_err(|e| format_err!("could not parse the deploy output: {}", e))?;
+        if deploy.program_id != self.program_key.to_string() {
+            return Err(anyhow!(
+                "deployed to {}, expected {}",
+                deploy.program_id,
+                self.program_key
+            ));
+        }
+        Ok(deploy.signature)
+    }
+
+    /// Where the buffer keypair of an in-progress upgrade of the program is saved.
+    pub fn buffer_keypair_path(&self, program: &str) -> PathBuf {
+        self.root
+            .join(".captain")
+            .join("buffers")
+            .join(format!("{}-{}.json", program, self.deploy_version))
+    }
+
+    /// Where the completed steps of a `deploy --resumable` of the program are saved.
+    pub fn deploy_progress_path(&self, program: &str) -> PathBuf {
+        self.root.join(".captain").join("deploys").join(format!(
+            "{}-{}-{}.json",
+            program, self.deploy_version, self.network
+        ))
+    }
+
+    /// Uses the given keypair, which may be a path or keypair URL, as the deployer
+    /// instead of the one in Captain.toml.
+    pub fn override_deployer(&mut self, deployer: &str) -> Result<()> {
+        keypair::validate(deployer)?;
+        self.deployer_path = keypair::file_path(deployer).unwrap_or_else(|| deployer.into());
+        Ok(())
+    }
+
+    /// Pays for deploys and buffer writes with `fee_payer` instead of the deployer.
+    pub fn set_fee_payer(&mut self, fee_payer: &str) -> Result<()> {
+        keypair::validate(fee_payer)?;
+        self.fee_payer = Some(fee_payer.to_string());
+        Ok(())
+    }
+
+    /// Arguments adding the fee payer, if any, and the additional signers to a deploy
+    /// or buffer write.
+    pub fn signer_args(&self) -> Vec<String> {
+        let fee_payer = self
+            .fee_payer
+            .iter()
+            .flat_map(|fee_payer| vec!["--fee-payer".to_string(), fee_payer.clone()]);
+        let signers = self
+            .signers
+            .iter()
+            .flat_map(|signer| vec!["--signer".to_string(), signer.clone()]);
+        fee_payer.chain(signers).collect()
+    }
+
+    /// Simulates deploying the program from `buffer`, the way the Solana CLI would
+    /// send it, without committing anything.
+    pub fn simulate_deploy(&self, buffer: &BufferInfo) -> Result<rpc::Simulation> {
+        let buffer_key: Pubkey = buffer
+            .address
+            .parse()
+            .map_err(|_| format_err!("invalid buffer address {}", buffer.address))?;
+        let deployer = self.deployer_pubkey()?;
+        let program_lamports = Rent::default().minimum_balance(
+            UpgradeableLoaderState::program_len().map_err(|e| format_err!("{}", e))?,
+        );
+        let instructions = solana_sdk::bpf_loader_upgradeable::deploy_with_max_program_len(
+            &deployer,
+            &self.program_key,
+            &buffer_key,
+            &deployer,
+            program_lamports,
+            // The Solana CLI leaves room for the program to double in size.
+            buffer.data_len * 2,
+        )
+        .map_err(|e| format_err!("could not build deploy instructions: {}", e))?;
+        let transaction = Transaction::new_with_payer(&instructions, Some(&deployer));
+        rpc::simulate_transaction(&self.network_url(), &transaction, command::commitment())
+    }
+
+    /// Grows the program data account by `additional_bytes`, paid by `payer`, or the
+    /// deployer if `None`.
+    pub fn extend_program(
+        &self,
+        additional_bytes: usize,
+        payer: Option<&str>,
+        max_retries: u32,
+    ) -> Result<()> {
+        let payer = payer.map_or_else(|| self.deployer_path.clone(), PathBuf::from);
+        command::exec_with_retries(
+            std::process::Command::new("solana")
+                .arg("--url")
+                .arg(self.network_url())
+                .arg("--commitment")
+                .arg(command::commitment().to_string())
+                .arg("--keypair")
+                .arg(payer)
+                .arg("program")
+                .arg("extend")
+                .arg(self.program_key.to_string())
+                .arg(additional_bytes.to_string()),
+            max_retries,
+        )?;
+        Ok(())
+    }
+
+    /// Transfers the upgrade authority of the program from the `upgrade_authority`
+    /// keypair, or the deployer if `None`, to `new_authority`, a pubkey or a keypair. The
+    /// deployer pays for the transaction. It is not retried: if a timed out attempt
+    /// landed, a retry would fail on the changed authority.
+    pub fn set_upgrade_authority(
+        &self,
+        upgrade_authority: Option<&str>,
+        new_authority: &str,
+    ) -> Result<()> {
+        // Newer Solana CLIs require the new authority to sign unless told otherwise, which
+        // only a keypair can.
+        let is_pubkey = new_authority.parse::<Pubkey>().is_ok();
+        command::exec(
+            solana_cmd!(self)
+                .arg("program")
+                .arg("set-upgrade-authority")
+                .arg(self.program_key.to_string())
+                .args(
+                    upgrade_authority
+                        .map(|keypair| vec!["--upgrade-authority", keypair])
+                        .unwrap_or_default(),
+                )
+                .arg("--new-upgrade-authority")
+                .arg(new_authority)
+                .args(is_pubkey.then_some("--skip-new-upgrade-authority-signer-check")),
+        )
+    }
+
+    /// Fetches a buffer to deploy from, erroring unless its authority is the deployer.
+    pub fn fetch_deployer_buffer(&self, buffer: &Pubkey) -> Result<BufferInfo> {
+        let deployer = self.deployer_pubkey()?;
+        if let Some(info) = fetch_buffers(&self.network_url(), &deployer)?
+            .into_iter()
+            .find(|info| info.address == buffer.to_string())
+        {
+            return Ok(info);
+        }
+        // Not one of the deployer's buffers: tell a missing buffer apart from one with
+        // another authority.
+        let output = command::exec_output(
+            solana_cmd!(self)
+                .arg("program")
+                .arg("show")
+                .arg(buffer.to_string())
+                .arg("--output")
+                .arg("json"),
+        )
+        .map_err(|_| format_err!("Buffer {} does not exist", buffer))?;
+        let info: BufferInfo = serde_json::from_str(&output)
+            .map_err(|e| format_err!("could not parse buffer {}: {}", buffer, e))?;
+        Err(CaptainError::AuthorityMismatch {
+            kind: AuthorityKind::Buffer,
+            account: info.address,
+            expected: deployer.to_string(),
+            actual: info.authority,
+        }
+        .into())
+    }
+
+    /// The deployer to show to users: its pubkey and where it was read from.
+    pub fn deployer_label(&self) -> String {
+        match self.deployer_pubkey() {
+            Ok(pubkey) => format!("{} ({})", pubkey, self.deployer_path.display()),
+            Err(_) => self.deployer_path.display().to_string(),
+        }
+    }
+
+    /// Pubkey of the deployer keypair.
+    pub fn deployer_pubkey(&self) -> Result<Pubkey> {
+        Ok(
+            solana_sdk::signer::keypair::read_keypair_file(&self.deployer_path)
+                .map_err(|_| {
+                    format_err!(
+                        "could not read deployer keypair {}",
+                        self.deployer_path.display()
+                    )
+                })?
+                .pubkey(),
+        )
+    }
+
+    /// Balance of the deployer, in lamports.
+    pub fn deployer_balance(&self) -> Result<u64> {
+        self.balance(None)
+    }
+
+    /// Balance of `address`, or of the deployer if `None`, in lamports.
+    fn balance(&self, address: Option<&Pubkey>) -> Result<u64> {
+        let output = command::exec_output(
+            solana_cmd!(self)
+                .arg("balance")
+                .args(address.map(Pubkey::to_string))
+                .arg("--lamports"),
+        )?;
+        output
+            .split_whitespace()
+            .next()
+            .and_then(|lamports| lamports.parse().ok())
+            .ok_or_else(|| format_err!("could not parse balance from {}", output.trim()))
+    }
+
+    /// Errors if whoever pays for deploys, i.e. the fee payer or else the deployer,
+    /// cannot pay for the given number of lamports. A fee payer on a device or behind a
+    /// prompt is not checked.
+    pub fn check_deployer_balance(&self, required: u64) -> Result<()> {
+        let (payer, balance) = match &self.fee_payer {
+            Some(fee_payer) => match keypair::pubkey(fee_payer)? {
+                Some(pubkey) => (
+                    format!("fee payer {}", fee_payer),
+                    self.balance(Some(&pubkey))?,
+                ),
+                None => return Ok(()),
+            },
+            None => (
+                format!("deployer {}", self.deployer_path.display()),
+                self.deployer_balance()?,
+            ),
+        };
+        if balance < required {
+            return Err(CaptainError::BalanceInsufficient {
+                balance: lamports_to_sol(balance),
+                required: lamports_to_sol(required),
+                payer,
+                airdrop_network: match (&self.fee_payer, &self.network) {
+                    (None, Network::Mainnet) | (Some(_), _) => None,
+                    (None, _) => Some(self.network.to_string()),
+                },
+            }
+            .into());
+        }
         Ok(())
     }
 
@@ -172,10 +1378,166 @@ impl Workspace {
         self.root.join("Anchor.toml").exists()
     }
 
+    /// Arguments setting the compute unit price of deploy transactions. A fee passed on
+    /// the command line takes precedence over the network's `priority_fee`.
+    pub fn priority_fee_args(&self, priority_fee: Option<u64>) -> Vec<String> {
+        priority_fee
+            .or(self.network_config.priority_fee)
+            .map(|fee| vec!["--with-compute-unit-price".to_string(), fee.to_string()])
+            .unwrap_or_default()
+    }
+
+    /// Arguments setting the compute unit limit of deploy transactions. A limit passed on
+    /// the command line takes precedence over the network's `compute_unit_limit`.
+    pub fn compute_unit_limit_args(&self, compute_unit_limit: Option<u32>) -> Result<Vec<String>> {
+        let limit = match compute_unit_limit.or(self.network_config.compute_unit_limit) {
+            Some(limit) => limit,
+            None => return Ok(vec![]),
+        };
+        if limit > MAX_COMPUTE_UNIT_LIMIT {
+            return Err(anyhow!(
+                "compute unit limit {} is above the maximum of {}",
+                limit,
+                MAX_COMPUTE_UNIT_LIMIT
+            ));
+        }
+        Ok(vec![
+            "--with-compute-unit-limit".to_string(),
+            limit.to_string(),
+        ])
+    }
+
     pub fn network_url(&self) -> String {
-        self.network_config
-            .url
+        self.network_url_override
             .clone()
+            .or_else(|| self.network_config.url.clone())
             .unwrap_or_else(|| self.network.url().to_string())
     }
+
+    /// Name of the network to show to users: the RPC URL if it was overridden on the
+    /// command line, and the network name otherwise.
+    pub fn network_label(&self) -> String {
+        self.network_url_override
+            .clone()
+            .unwrap_or_else(|| self.network.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(root: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .current_dir(root)
+            .args([
+                "-c",
+                "user.name=captain",
+                "-c",
+                "user.email=captain@example.com",
+            ])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// A git repo with programs `foo`, `bar` and `old-prog` committed at `HEAD`.
+    fn repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for program in ["foo", "bar", "old-prog"] {
+            write(
+                root,
+                &format!("programs/{}/Cargo.toml", program),
+                &format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", program),
+            );
+            write(root, &format!("programs/{}/src/lib.rs", program), "");
+        }
+        write(root, ".gitignore", "target/\n");
+        git(root, &["init", "-q"]);
+        git(root, &["add", "-A"]);
+        git(root, &["commit", "-q", "-m", "init"]);
+        dir
+    }
+
+    fn changed(root: &Path, programs: &[&str]) -> Vec<bool> {
+        let programs: Vec<String> = programs.iter().map(|p| p.to_string()).collect();
+        changed_since(root, "HEAD", &programs).unwrap()
+    }
+
+    #[test]
+    fn parse_idl_buffer_from_anchor_output() {
+        let buffer: Pubkey = "8TqqugH88U3fDEWeKHqBSxZKeqpGj46VwxFTvYAjuy5K"
+            .parse()
+            .unwrap();
+        for output in [
+            include_str!("../tests/fixtures/anchor/idl-write-buffer.txt"),
+            include_str!("../tests/fixtures/anchor/idl-write-buffer-warning.txt"),
+        ] {
+            assert_eq!(parse_idl_buffer(output).unwrap(), buffer);
+        }
+    }
+
+    #[test]
+    fn parse_idl_buffer_without_buffer() {
+        assert!(parse_idl_buffer("").is_err());
+        assert!(parse_idl_buffer("Error: Account not found").is_err());
+        assert!(parse_idl_buffer("Idl buffer created: notapubkey").is_err());
+    }
+
+    #[test]
+    fn changed_since_nothing_changed() {
+        let dir = repo();
+        write(dir.path(), "target/deploy/foo.so", "ignored");
+        assert_eq!(changed(dir.path(), &["foo", "bar"]), vec![false, false]);
+    }
+
+    #[test]
+    fn changed_since_modified_and_untracked_files() {
+        let dir = repo();
+        write(dir.path(), "programs/foo/src/lib.rs", "// changed");
+        write(dir.path(), "programs/bar/src/new.rs", "");
+        assert_eq!(
+            changed(dir.path(), &["foo", "bar", "old_prog"]),
+            vec![true, true, false]
+        );
+    }
+
+    #[test]
+    fn changed_since_renamed_file() {
+        let dir = repo();
+        git(
+            dir.path(),
+            &["mv", "programs/foo/src/lib.rs", "programs/bar/src/foo.rs"],
+        );
+        assert_eq!(
+            changed(dir.path(), &["foo", "bar", "old_prog"]),
+            vec![true, true, false]
+        );
+    }
+
+    #[test]
+    fn changed_since_deleted_program() {
+        let dir = repo();
+        git(dir.path(), &["rm", "-q", "-r", "programs/old-prog"]);
+        git(dir.path(), &["commit", "-q", "-m", "remove old-prog"]);
+        let programs = vec!["foo".to_string(), "old_prog".to_string()];
+        assert_eq!(
+            changed_since(dir.path(), "HEAD~1", &programs).unwrap(),
+            vec![false, true]
+        );
+        assert_eq!(
+            changed(dir.path(), &["foo", "old_prog"]),
+            vec![false, false]
+        );
+    }
 }
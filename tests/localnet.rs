@@ -0,0 +1,281 @@
+//! End-to-end tests which build a tiny program and deploy and upgrade it on a
+//! `solana-test-validator` with the real Solana CLI. They need the Solana CLI and
+//! toolchain on PATH, so they are ignored by default. Run them with:
+//!
+//! ```text
+//! cargo test --test localnet -- --ignored --test-threads 1
+//! ```
+use captain::rpc;
+use captain::solana::ProgramInfo;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{write_keypair_file, Keypair, Signer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Ports which do not collide with a validator already running on the default ones.
+const RPC_PORT: u16 = 18899;
+const FAUCET_PORT: u16 = 19900;
+
+/// A program which does nothing, so that it builds without any dependencies. `marker`
+/// makes each version's binary differ.
+fn program_source(marker: u64) -> String {
+    format!(
+        r#"#[no_mangle]
+pub static MARKER: u64 = {};
+
+#[no_mangle]
+pub extern "C" fn entrypoint(_input: *mut u8) -> u64 {{
+    0
+}}
+"#,
+        marker
+    )
+}
+
+fn program_manifest(version: &str) -> String {
+    format!(
+        r#"[package]
+name = "noop"
+version = "{}"
+edition = "2018"
+
+[lib]
+crate-type = ["cdylib", "lib"]
+"#,
+        version
+    )
+}
+
+/// `solana-test-validator` with an empty ledger, killed when dropped.
+struct TestValidator {
+    child: Child,
+    url: String,
+}
+
+impl TestValidator {
+    fn start(ledger: &Path) -> Self {
+        let child = Command::new("solana-test-validator")
+            .arg("--ledger")
+            .arg(ledger)
+            .arg("--reset")
+            .arg("--quiet")
+            .arg("--rpc-port")
+            .arg(RPC_PORT.to_string())
+            .arg("--faucet-port")
+            .arg(FAUCET_PORT.to_string())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("solana-test-validator must be on PATH");
+        let validator = TestValidator {
+            child,
+            url: format!("http://127.0.0.1:{}", RPC_PORT),
+        };
+        let healthy = rpc::poll_until(
+            || Ok(rpc::health(&validator.url).ok()),
+            rpc::Backoff {
+                initial: Duration::from_millis(250),
+                max: Duration::from_secs(2),
+            },
+            Duration::from_secs(60),
+        )
+        .unwrap();
+        assert!(
+            healthy.is_some(),
+            "solana-test-validator did not become healthy"
+        );
+        validator
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Airdrops SOL to an account other than a deployer, e.g. the upgrade authority, which
+/// pays for the upgrade transaction.
+fn airdrop(url: &str, address: &Pubkey) {
+    let status = Command::new("solana")
+        .arg("--url")
+        .arg(url)
+        .arg("airdrop")
+        .arg("10")
+        .arg(address.to_string())
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "could not airdrop to {}", address);
+}
+
+/// A Captain workspace with a single program, `noop`, deploying to the validator.
+struct TestWorkspace {
+    dir: TempDir,
+    program_id: Pubkey,
+    upgrade_authority: Pubkey,
+}
+
+impl TestWorkspace {
+    fn new(validator: &TestValidator) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"programs/*\"]\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.join("programs/noop/src")).unwrap();
+
+        let keys = root.join(".captain");
+        fs::create_dir_all(keys.join("program_keypairs")).unwrap();
+        let deployer = Keypair::new();
+        write_keypair_file(&deployer, keys.join("deployer.json")).unwrap();
+        let upgrade_authority = Keypair::new();
+        write_keypair_file(&upgrade_authority, keys.join("upgrade_authority.json")).unwrap();
+        let program = Keypair::new();
+        write_keypair_file(&program, keys.join("program_keypairs/noop-0.x.json")).unwrap();
+
+        fs::write(
+            root.join("Captain.toml"),
+            format!(
+                r#"[paths]
+artifacts = "./.captain/artifacts/"
+program_keypairs = "./.captain/program_keypairs"
+
+[networks.localnet]
+deployer = "./.captain/deployer.json"
+upgrade_authority = "{}"
+url = "{}"
+
+[programs.noop]
+address = "{}"
+"#,
+                upgrade_authority.pubkey(),
+                validator.url,
+                program.pubkey()
+            ),
+        )
+        .unwrap();
+
+        let workspace = TestWorkspace {
+            dir,
+            program_id: program.pubkey(),
+            upgrade_authority: upgrade_authority.pubkey(),
+        };
+        workspace.write_program("0.1.0", 1);
+        workspace
+    }
+
+    fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn upgrade_authority_path(&self) -> PathBuf {
+        self.root().join(".captain/upgrade_authority.json")
+    }
+
+    /// Writes, builds and releases version `version` of the program. Captain expects an
+    /// IDL even outside of Anchor workspaces, so an empty one is written too.
+    fn write_program(&self, version: &str, marker: u64) {
+        let program = self.root().join("programs/noop");
+        fs::write(program.join("Cargo.toml"), program_manifest(version)).unwrap();
+        fs::write(program.join("src/lib.rs"), program_source(marker)).unwrap();
+        self.captain(&["build"]);
+        fs::create_dir_all(self.root().join("target/idl")).unwrap();
+        fs::write(self.root().join("target/idl/noop.json"), "{}").unwrap();
+        self.captain(&["release", "--program", "noop"]);
+    }
+
+    /// Runs the captain binary in the workspace, panicking if it fails.
+    fn captain(&self, args: &[&str]) -> String {
+        let output = Command::new(env!("CARGO_BIN_EXE_captain"))
+            .current_dir(self.root())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "captain {} failed:\n{}\n{}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    fn fetch_program(&self, url: &str) -> ProgramInfo {
+        let output = Command::new("solana")
+            .arg("--url")
+            .arg(url)
+            .arg("program")
+            .arg("show")
+            .arg(self.program_id.to_string())
+            .arg("--output")
+            .arg("json")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "program {} not found",
+            self.program_id
+        );
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+}
+
+#[test]
+#[ignore]
+fn deploy_then_upgrade() {
+    let ledger = tempfile::tempdir().unwrap();
+    let validator = TestValidator::start(ledger.path());
+    let workspace = TestWorkspace::new(&validator);
+    workspace.captain(&["fund", "--network", "localnet", "--amount", "100"]);
+
+    workspace.captain(&["deploy", "--program", "noop", "--network", "localnet"]);
+    let deployed = workspace.fetch_program(&validator.url);
+    assert_eq!(deployed.program_id, workspace.program_id.to_string());
+    assert_eq!(deployed.authority, workspace.upgrade_authority.to_string());
+
+    workspace.write_program("0.1.1", 2);
+    airdrop(&validator.url, &workspace.upgrade_authority);
+    let upgrade_authority = workspace.upgrade_authority_path();
+    workspace.captain(&[
+        "upgrade",
+        "--program",
+        "noop",
+        "--network",
+        "localnet",
+        "--upgrade-authority",
+        upgrade_authority.to_str().unwrap(),
+    ]);
+    let upgraded = workspace.fetch_program(&validator.url);
+    assert_eq!(upgraded.authority, workspace.upgrade_authority.to_string());
+    assert!(upgraded.last_deploy_slot > deployed.last_deploy_slot);
+}
+
+#[test]
+#[ignore]
+fn redeploy_is_up_to_date() {
+    let ledger = tempfile::tempdir().unwrap();
+    let validator = TestValidator::start(ledger.path());
+    let workspace = TestWorkspace::new(&validator);
+    workspace.captain(&["fund", "--network", "localnet", "--amount", "100"]);
+
+    workspace.captain(&["deploy", "--program", "noop", "--network", "localnet"]);
+    let output = workspace.captain(&[
+        "--output",
+        "json",
+        "deploy",
+        "--program",
+        "noop",
+        "--network",
+        "localnet",
+    ]);
+    let result: serde_json::Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(result["already_deployed"], true);
+    assert_eq!(result["up_to_date"], true);
+}
@@ -0,0 +1,145 @@
+//! Loads the fixture workspace in `tests/fixtures/workspace` through the library and
+//! releases its program, without the Solana CLI.
+use captain::config::{self, Network};
+use captain::workspace::{self, ProgramIdOverride, ProgramOverrides};
+use semver::Version;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{write_keypair_file, Keypair, Signer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let dest = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &dest);
+        } else {
+            fs::copy(&path, &dest).unwrap();
+        }
+    }
+}
+
+/// A copy of the fixture workspace with a deployer, so that loading it may write to it.
+struct Fixture {
+    dir: TempDir,
+    program_keypair: Keypair,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/workspace"),
+            dir.path(),
+        );
+        write_keypair_file(&Keypair::new(), dir.path().join("deployer.json")).unwrap();
+        // Binaries and keypairs are not checked in, so the program comes prebuilt.
+        let program_keypair = Keypair::new();
+        write_keypair_file(&program_keypair, dir.path().join("foo-keypair.json")).unwrap();
+        fs::write(dir.path().join("foo.so"), b"\x7fELF not really a program").unwrap();
+        Fixture {
+            dir,
+            program_keypair,
+        }
+    }
+
+    fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.root().join("Captain.toml")
+    }
+
+    fn overrides(&self) -> ProgramOverrides {
+        ProgramOverrides {
+            bin: Some(self.root().join("foo.so")),
+            id: Some(ProgramIdOverride::Keypair(
+                self.root().join("foo-keypair.json"),
+            )),
+        }
+    }
+
+    fn load(&self) -> anyhow::Result<workspace::Workspace> {
+        workspace::load_with_overrides(
+            "foo",
+            None,
+            Network::Localnet,
+            Some(&self.config_path()),
+            &self.overrides(),
+        )
+    }
+}
+
+#[test]
+fn loads_fixture_workspace() {
+    let fixture = Fixture::new();
+    let workspace = fixture.load().unwrap();
+    assert_eq!(workspace.deploy_version, Version::new(0, 1, 0));
+    assert_eq!(workspace.program_key, fixture.program_keypair.pubkey());
+    assert_eq!(workspace.program_paths.bin, fixture.root().join("foo.so"));
+    assert!(workspace.program_paths.idl.ends_with("idl/foo.json"));
+    assert!(workspace.deployer_path.ends_with("deployer.json"));
+    assert_eq!(workspace.network, Network::Localnet);
+    assert!(!workspace.has_anchor());
+}
+
+#[test]
+fn releases_fixture_program() {
+    let fixture = Fixture::new();
+    let workspace = fixture.load().unwrap();
+    assert!(!workspace.artifact_paths.exist());
+
+    workspace.copy_artifacts().unwrap();
+    assert!(workspace.artifact_paths.exist());
+    let bin = fs::read(fixture.root().join("foo.so")).unwrap();
+    assert_eq!(fs::read(&workspace.artifact_paths.bin).unwrap(), bin);
+    assert_eq!(
+        workspace::read_sha256(&workspace.artifact_paths.sha256).unwrap(),
+        Some(workspace::sha256_hex(&bin))
+    );
+
+    // The released version is picked up as the latest artifact.
+    let (config, _, _) = config::Config::discover(Some(&fixture.config_path())).unwrap();
+    assert_eq!(
+        config.artifact_versions("foo").unwrap(),
+        vec![Version::new(0, 1, 0)]
+    );
+}
+
+#[test]
+fn rejects_mismatched_program_address() {
+    let fixture = Fixture::new();
+    config::set_value(
+        &fixture.config_path(),
+        "programs.foo.address",
+        &Pubkey::new_unique().to_string(),
+    )
+    .unwrap();
+    let err = fixture.load().err().unwrap().to_string();
+    assert!(err.contains("is declared with address"), "{}", err);
+
+    config::set_value(
+        &fixture.config_path(),
+        "programs.foo.address",
+        &fixture.program_keypair.pubkey().to_string(),
+    )
+    .unwrap();
+    fixture.load().unwrap();
+}
+
+#[test]
+fn rejects_networks_the_program_is_not_allowed_on() {
+    let fixture = Fixture::new();
+    config::set_value(
+        &fixture.config_path(),
+        "programs.foo.networks",
+        r#"["devnet"]"#,
+    )
+    .unwrap();
+    let err = fixture.load().err().unwrap().to_string();
+    assert!(err.contains("may not be deployed to localnet"), "{}", err);
+}